@@ -0,0 +1,629 @@
+//! `#[derive(Serialize, DeSerialize)]` for serialize-rs.
+//!
+//! Structs are written field by field in declaration order, exactly like
+//! `serialize_struct!`/`deserialize_struct!`. `DeSerialize` also derives
+//! `Decode`, which builds the value field by field and needs no `Default`;
+//! it is left out when a field's type has no `Decode` impl, such as a
+//! hand-written `DeSerialize` without `decode_default!`. Enums are written as
+//! a `u32` tag followed by the fields of that variant. The tag is the variant's
+//! `#[serialize(tag = n)]`, else one more than the tag before it, starting at
+//! 0, so without attributes it is the variant index; discriminants don't
+//! affect it. Use the `derive` feature of serialize-rs rather than depending
+//! on this crate directly.
+//!
+//! Fields accept `#[serialize(...)]` with
+//!
+//! - `skip`: not written; decoded as `Default::default()`, or as the result of
+//!   `default = "path::to::fn"` when given.
+//! - `with = "module"`: written by `module::serialize_to(&field, e)` and read
+//!   by `module::decode_from(d)`, with the signatures of the trait methods.
+//! - `bound = "T: Trait"`: where-clause used instead of the inferred one for
+//!   this field, or `bound(serialize = "...", deserialize = "...", decode =
+//!   "...")` to give each impl its own. `decode` defaults to `deserialize`.
+//! - `order = n`: position on the wire; fields without it keep their index.
+//!   Two written fields with the same position are rejected:
+//!
+//! ```compile_fail
+//! #[derive(serialize_rs::Serialize)]
+//! struct Point {
+//!     #[serialize(order = 1)]
+//!     x: u8,
+//!     y: u8,
+//! }
+//! ```
+//!
+//! The container accepts `bound` (replaces all inferred bounds), `crate =
+//! "path"` (where to find serialize-rs when it is renamed or re-exported),
+//! `endian = "big" | "little"`, `length_width = "u8" | "u16" | "u32" | "u64" |
+//! "varint"` and `int_encoding = "fixed" | "varint"`, which override the
+//! encoder's config for the whole value. Enums also take `tag_width = "u8" |
+//! "u16" | "u32" | "varint"`.
+
+extern crate proc_macro;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Span, TokenStream as TokenStream2, TokenTree};
+use quote::{format_ident, quote, ToTokens};
+use syn::meta::ParseNestedMeta;
+use syn::punctuated::Punctuated;
+use syn::{parse_macro_input, parse_quote, Attribute, Data, DataEnum, DeriveInput, Fields, Generics, Index,
+    LitInt, LitStr, Member, Token, Type, WherePredicate};
+
+#[proc_macro_derive(Serialize, attributes(serialize))]
+pub fn derive_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_serialize(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+#[proc_macro_derive(DeSerialize, attributes(serialize))]
+pub fn derive_deserialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_deserialize(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+// Explicit where-clauses for the `Serialize`, `DeSerialize` and `Decode` impl.
+#[derive(Default)]
+struct Bound {
+    serialize: Option<Vec<WherePredicate>>,
+    deserialize: Option<Vec<WherePredicate>>,
+    decode: Option<Vec<WherePredicate>>,
+}
+
+impl Bound {
+    fn decode(&self) -> &Option<Vec<WherePredicate>> {
+        if self.decode.is_some() { &self.decode } else { &self.deserialize }
+    }
+}
+
+#[derive(Default)]
+struct ContainerAttrs {
+    bound: Bound,
+    // `TagWidth` variant and the largest tag it holds
+    tag_width: Option<(TokenStream2, u64)>,
+    // `Config` builder calls applied to the encoder's config
+    config: Vec<TokenStream2>,
+    // path of the serialize-rs crate, `::serialize_rs` unless overridden
+    krate: Option<syn::Path>,
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    skip: bool,
+    default: Option<syn::Path>,
+    with: Option<syn::Path>,
+    bound: Bound,
+    order: Option<i64>,
+}
+
+fn parse_predicates(lit: &LitStr) -> syn::Result<Vec<WherePredicate>> {
+    let predicates = lit.parse_with(Punctuated::<WherePredicate, Token![,]>::parse_terminated)?;
+    Ok(predicates.into_iter().collect())
+}
+
+// `bound = "..."` for all impls or `bound(serialize = "...", deserialize = "...", decode = "...")`.
+fn parse_bound(meta: &ParseNestedMeta, bound: &mut Bound) -> syn::Result<()> {
+    if meta.input.peek(Token![=]) {
+        let predicates = parse_predicates(&meta.value()?.parse()?)?;
+        bound.serialize = Some(predicates.clone());
+        bound.deserialize = Some(predicates.clone());
+        bound.decode = Some(predicates);
+        return Ok(());
+    }
+    meta.parse_nested_meta(|meta| {
+        if meta.path.is_ident("serialize") {
+            bound.serialize = Some(parse_predicates(&meta.value()?.parse()?)?);
+        } else if meta.path.is_ident("deserialize") {
+            bound.deserialize = Some(parse_predicates(&meta.value()?.parse()?)?);
+        } else if meta.path.is_ident("decode") {
+            bound.decode = Some(parse_predicates(&meta.value()?.parse()?)?);
+        } else {
+            return Err(meta.error("expected `serialize`, `deserialize` or `decode`"));
+        }
+        Ok(())
+    })
+}
+
+fn container_attrs(attrs: &[Attribute]) -> syn::Result<ContainerAttrs> {
+    let mut result = ContainerAttrs::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serialize")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("bound") {
+                parse_bound(&meta, &mut result.bound)?;
+            } else if meta.path.is_ident("crate") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.krate = Some(lit.parse()?);
+            } else if meta.path.is_ident("endian") {
+                let lit: LitStr = meta.value()?.parse()?;
+                let endian = match lit.value().as_str() {
+                    "big" => quote!(Big),
+                    "little" => quote!(Little),
+                    _ => return Err(syn::Error::new(lit.span(), "expected \"big\" or \"little\"")),
+                };
+                result.config.push(quote!(.with_endian(_serialize_rs::Endian::#endian)));
+            } else if meta.path.is_ident("length_width") {
+                let lit: LitStr = meta.value()?.parse()?;
+                let width = match lit.value().as_str() {
+                    "u8" => quote!(U8),
+                    "u16" => quote!(U16),
+                    "u32" => quote!(U32),
+                    "u64" => quote!(U64),
+                    "varint" => quote!(Varint),
+                    _ => return Err(syn::Error::new(lit.span(), "expected \"u8\", \"u16\", \"u32\", \"u64\" or \"varint\"")),
+                };
+                result.config.push(quote!(.with_length_width(_serialize_rs::LengthWidth::#width)));
+            } else if meta.path.is_ident("tag_width") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.tag_width = Some(match lit.value().as_str() {
+                    "u8" => (quote!(U8), u8::MAX as u64),
+                    "u16" => (quote!(U16), u16::MAX as u64),
+                    "u32" => (quote!(U32), u32::MAX as u64),
+                    "varint" => (quote!(Varint), u32::MAX as u64),
+                    _ => return Err(syn::Error::new(lit.span(), "expected \"u8\", \"u16\", \"u32\" or \"varint\"")),
+                });
+            } else if meta.path.is_ident("int_encoding") {
+                let lit: LitStr = meta.value()?.parse()?;
+                let encoding = match lit.value().as_str() {
+                    "fixed" => quote!(Fixed),
+                    "varint" => quote!(Varint),
+                    _ => return Err(syn::Error::new(lit.span(), "expected \"fixed\" or \"varint\"")),
+                };
+                result.config.push(quote!(.with_int_encoding(_serialize_rs::IntEncoding::#encoding)));
+            } else {
+                return Err(meta.error("unknown serialize container attribute"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(result)
+}
+
+fn field_attrs(attrs: &[Attribute]) -> syn::Result<FieldAttrs> {
+    let mut result = FieldAttrs::default();
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("serialize")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("skip") {
+                result.skip = true;
+            } else if meta.path.is_ident("default") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.default = Some(lit.parse()?);
+            } else if meta.path.is_ident("with") {
+                let lit: LitStr = meta.value()?.parse()?;
+                result.with = Some(lit.parse()?);
+            } else if meta.path.is_ident("bound") {
+                parse_bound(&meta, &mut result.bound)?;
+            } else if meta.path.is_ident("order") {
+                let lit: LitInt = meta.value()?.parse()?;
+                result.order = Some(lit.base10_parse()?);
+            } else {
+                return Err(meta.error("unknown serialize field attribute"));
+            }
+            Ok(())
+        })?;
+        if result.default.is_some() && !result.skip {
+            return Err(syn::Error::new_spanned(attr, "`default` only applies to skipped fields"));
+        }
+    }
+    Ok(result)
+}
+
+// Tags of the variants of `data`: `#[serialize(tag = n)]`, else one more than
+// the previous variant's tag.
+fn tags(data: &DataEnum, container: &ContainerAttrs) -> syn::Result<Vec<u32>> {
+    let max = container.tag_width.as_ref().map_or(u32::MAX as u64, |(_, max)| *max);
+    let mut tags: Vec<u32> = Vec::new();
+    for variant in data.variants.iter() {
+        let mut tag: Option<(u64, Span)> = None;
+        for attr in variant.attrs.iter().filter(|attr| attr.path().is_ident("serialize")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("tag") {
+                    let lit: LitInt = meta.value()?.parse()?;
+                    tag = Some((lit.base10_parse()?, lit.span()));
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown serialize variant attribute"))
+                }
+            })?;
+        }
+        let (tag, span) = match tag {
+            Some(tag) => tag,
+            None => (tags.last().map_or(0, |last| *last as u64 + 1), variant.ident.span()),
+        };
+        if tag > max {
+            return Err(syn::Error::new(span, format!("tag {} does not fit into the tag width", tag)));
+        }
+        if tags.contains(&(tag as u32)) {
+            return Err(syn::Error::new(span, format!("duplicate tag {}", tag)));
+        }
+        tags.push(tag as u32);
+    }
+    Ok(tags)
+}
+
+fn tag_width(container: &ContainerAttrs) -> TokenStream2 {
+    let width = container.tag_width.as_ref().map_or(quote!(U32), |(width, _)| width.clone());
+    quote!(_serialize_rs::TagWidth::#width)
+}
+
+struct Field<'a> {
+    member: Member,
+    // name reported in error paths
+    label: String,
+    // local the field is bound to, prefixed so it can't shadow the coder
+    binding: syn::Ident,
+    ty: &'a Type,
+    attrs: FieldAttrs,
+}
+
+// The fields of a struct or variant, sorted into wire order.
+fn fields(fields: &Fields) -> syn::Result<Vec<Field<'_>>> {
+    let mut result: Vec<(i64, Field)> = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let (member, label, binding) = match &field.ident {
+            Some(ident) => (Member::Named(ident.clone()), ident.to_string(), format_ident!("__field_{}", ident)),
+            None => (Member::Unnamed(Index::from(i)), i.to_string(), format_ident!("__field{}", i)),
+        };
+        let attrs = field_attrs(&field.attrs)?;
+        let order = attrs.order.unwrap_or(i as i64);
+        // skipped fields have no position on the wire to collide over
+        if !attrs.skip && result.iter().any(|(other, other_field)| !other_field.attrs.skip && *other == order) {
+            return Err(syn::Error::new_spanned(field, format!("duplicate order {}", order)));
+        }
+        result.push((order, Field { member, label, binding, ty: &field.ty, attrs }));
+    }
+    result.sort_by_key(|(order, _)| *order);
+    Ok(result.into_iter().map(|(_, field)| field).collect())
+}
+
+fn all_fields(data: &Data) -> syn::Result<Vec<Field<'_>>> {
+    match data {
+        Data::Struct(data) => fields(&data.fields),
+        Data::Enum(data) => {
+            let mut result = Vec::new();
+            for variant in data.variants.iter() {
+                result.extend(fields(&variant.fields)?);
+            }
+            Ok(result)
+        },
+        Data::Union(_) => Ok(Vec::new()),
+    }
+}
+
+fn mentions(tokens: TokenStream2, params: &[syn::Ident]) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(ident) => params.contains(&ident),
+        TokenTree::Group(group) => mentions(group.stream(), params),
+        _ => false,
+    })
+}
+
+// Adds `field_type: bound` for every field whose type uses a type parameter,
+// so that e.g. `Vec<T>` asks for what `Vec<T>` needs rather than `T: bound`.
+// Explicit `bound` attributes replace the inferred predicates.
+fn with_bound<B, F>(generics: &Generics, container: &ContainerAttrs, fields: &[Field], explicit: B, bound: F) -> Generics
+    where B: Fn(&Bound) -> &Option<Vec<WherePredicate>>, F: Fn(&Field) -> Option<TokenStream2> {
+    let params: Vec<syn::Ident> = generics.type_params().map(|param| param.ident.clone()).collect();
+    let mut generics = generics.clone();
+    let where_clause = generics.make_where_clause();
+    if let Some(predicates) = explicit(&container.bound) {
+        where_clause.predicates.extend(predicates.iter().cloned());
+        return generics;
+    }
+    for field in fields {
+        if let Some(predicates) = explicit(&field.attrs.bound) {
+            where_clause.predicates.extend(predicates.iter().cloned());
+        } else if mentions(field.ty.to_token_stream(), &params) {
+            if let Some(bound) = bound(field) {
+                let ty = field.ty;
+                where_clause.predicates.push(parse_quote!(#ty: #bound));
+            }
+        }
+    }
+    generics
+}
+
+// The `Decode` where-clause: the inferred or explicit bounds, plus a
+// higher-ranked `field_type: bound` for every field type without a type
+// parameter. Those are not checked where the impl is defined, so a field that
+// only implements `DeSerialize` leaves the type without `Decode` instead of
+// failing to build.
+fn with_decode_bound(generics: &Generics, container: &ContainerAttrs, fields: &[Field]) -> Generics {
+    let params: Vec<syn::Ident> = generics.type_params().map(|param| param.ident.clone()).collect();
+    let mut result = with_bound(generics, container, fields, Bound::decode, decode_bound);
+    let where_clause = result.make_where_clause();
+    for field in fields {
+        if !mentions(field.ty.to_token_stream(), &params) {
+            if let Some(bound) = decode_bound(field) {
+                let ty = field.ty;
+                where_clause.predicates.push(parse_quote!(for<'__a> #ty: #bound));
+            }
+        }
+    }
+    result
+}
+
+// Wraps `body`, a block evaluating to a `Result`, so that it runs with the
+// container's config overrides.
+fn scoped(container: &ContainerAttrs, coder: TokenStream2, body: TokenStream2) -> TokenStream2 {
+    if container.config.is_empty() {
+        return body;
+    }
+    let config = &container.config;
+    quote! {
+        let config = #coder.config() #(#config)*;
+        #coder.scoped(config, |#coder| {
+            #body
+        })
+    }
+}
+
+// Puts the impls in an anonymous const that names the serialize-rs crate
+// `_serialize_rs`, the path every generated item goes through.
+fn wrap(container: &ContainerAttrs, impls: TokenStream2) -> TokenStream2 {
+    let krate = match &container.krate {
+        Some(krate) => quote!(#krate),
+        None => quote!(::serialize_rs),
+    };
+    quote! {
+        const _: () = {
+            use #krate as _serialize_rs;
+
+            #impls
+        };
+    }
+}
+
+fn serialize_field(field: &Field, value: TokenStream2) -> TokenStream2 {
+    match &field.attrs.with {
+        Some(with) => quote!(#with::serialize_to(#value, e)?;),
+        None => quote!(_serialize_rs::Serialize::serialize_to(#value, e)?;),
+    }
+}
+
+// Expression building a fresh value for `field`.
+fn decode_field(field: &Field) -> TokenStream2 {
+    let label = &field.label;
+    if field.attrs.skip {
+        return match &field.attrs.default {
+            Some(default) => quote!(#default()),
+            None => quote!(::std::default::Default::default()),
+        };
+    }
+    match &field.attrs.with {
+        Some(with) => quote! {
+            d.nested(_serialize_rs::PathSegment::Field(#label), |d| #with::decode_from(d))?
+        },
+        None => quote! {
+            d.nested(_serialize_rs::PathSegment::Field(#label), |d| _serialize_rs::Decode::decode_from(d))?
+        },
+    }
+}
+
+fn expand_serialize(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let container = container_attrs(&input.attrs)?;
+    let generics = with_bound(&input.generics, &container, &all_fields(&input.data)?, |bound| &bound.serialize, |field| {
+        if field.attrs.skip || field.attrs.with.is_some() {
+            None
+        } else {
+            Some(quote!(_serialize_rs::Serialize))
+        }
+    });
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let body = match &input.data {
+        Data::Struct(data) => {
+            let fields = fields(&data.fields)?;
+            let writes = fields.iter().filter(|field| !field.attrs.skip).map(|field| {
+                let member = &field.member;
+                serialize_field(field, quote!(&self.#member))
+            });
+            quote! {
+                #(#writes)*
+                ::std::result::Result::Ok(())
+            }
+        },
+        Data::Enum(data) => {
+            let width = tag_width(&container);
+            let mut arms = Vec::new();
+            for (tag, variant) in tags(data, &container)?.into_iter().zip(data.variants.iter()) {
+                let ident = &variant.ident;
+                let fields = fields(&variant.fields)?;
+                let members = fields.iter().map(|field| &field.member);
+                let bindings = fields.iter().map(|field| &field.binding);
+                let writes = fields.iter().filter(|field| !field.attrs.skip).map(|field| {
+                    let binding = &field.binding;
+                    serialize_field(field, quote!(#binding))
+                });
+                arms.push(quote! {
+                    #name::#ident { #(#members: #bindings,)* } => {
+                        e.write_tag(#width, #tag)?;
+                        #(#writes)*
+                    },
+                });
+            }
+            quote! {
+                match self {
+                    #(#arms)*
+                }
+                ::std::result::Result::Ok(())
+            }
+        },
+        Data::Union(_) => return Err(syn::Error::new(Span::call_site(), "unions can not derive Serialize")),
+    };
+    let body = scoped(&container, quote!(e), body);
+
+    Ok(wrap(&container, quote! {
+        impl #impl_generics _serialize_rs::Serialize for #name #ty_generics #where_clause {
+            _serialize_rs::__dyn_method!(serialize);
+
+            #[allow(unused_variables)]
+            fn serialize_to<__W: ::std::io::Write>(&self, e: &mut _serialize_rs::Encoder<__W>) -> ::std::result::Result<(), _serialize_rs::Error> {
+                #body
+            }
+        }
+    }))
+}
+
+// The `Decode` bound of a field in an impl building the value from scratch.
+fn decode_bound(field: &Field) -> Option<TokenStream2> {
+    if field.attrs.skip {
+        match field.attrs.default {
+            Some(_) => None,
+            None => Some(quote!(::std::default::Default)),
+        }
+    } else if field.attrs.with.is_some() {
+        None
+    } else {
+        Some(quote!(_serialize_rs::Decode))
+    }
+}
+
+// Expression for the placeholder of `field`, or `None` when a `with` module
+// provides none.
+fn placeholder_field(field: &Field) -> Option<TokenStream2> {
+    if field.attrs.skip {
+        return Some(decode_field(field));
+    }
+    if field.attrs.with.is_some() {
+        return None;
+    }
+    let ty = field.ty;
+    Some(quote!(<#ty as _serialize_rs::Decode>::placeholder()?))
+}
+
+fn expand_deserialize(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let container = container_attrs(&input.attrs)?;
+    let all_fields = all_fields(&input.data)?;
+
+    // structs are filled in place; a different variant can not be, so an enum
+    // is decoded from scratch and assigned
+    let (deserialize, decode, placeholder) = match &input.data {
+        Data::Struct(data) => {
+            let fields = fields(&data.fields)?;
+            let reads = fields.iter().map(|field| {
+                let member = &field.member;
+                let label = &field.label;
+                if field.attrs.skip || field.attrs.with.is_some() {
+                    let value = decode_field(field);
+                    quote!(self.#member = #value;)
+                } else {
+                    quote! {
+                        d.nested(_serialize_rs::PathSegment::Field(#label),
+                            |d| _serialize_rs::DeSerialize::deserialize_from(&mut self.#member, d))?;
+                    }
+                }
+            });
+            let deserialize = quote! {
+                #(#reads)*
+                ::std::result::Result::Ok(())
+            };
+            let members: Vec<&Member> = fields.iter().map(|field| &field.member).collect();
+            let bindings: Vec<&syn::Ident> = fields.iter().map(|field| &field.binding).collect();
+            let reads = fields.iter().map(|field| {
+                let binding = &field.binding;
+                let value = decode_field(field);
+                quote!(let #binding = #value;)
+            });
+            let decode = quote! {
+                #(#reads)*
+                ::std::result::Result::Ok(#name { #(#members: #bindings,)* })
+            };
+            let placeholders: Option<Vec<TokenStream2>> = fields.iter().map(placeholder_field).collect();
+            let placeholder = placeholders.map(|placeholders| quote! {
+                ::std::option::Option::Some(#name { #(#members: #placeholders,)* })
+            });
+            (deserialize, decode, placeholder)
+        },
+        Data::Enum(data) => {
+            let width = tag_width(&container);
+            let mut arms = Vec::new();
+            for (tag, variant) in tags(data, &container)?.into_iter().zip(data.variants.iter()) {
+                let ident = &variant.ident;
+                let fields = fields(&variant.fields)?;
+                let members = fields.iter().map(|field| &field.member);
+                let bindings = fields.iter().map(|field| &field.binding);
+                let reads = fields.iter().map(|field| {
+                    let binding = &field.binding;
+                    let value = decode_field(field);
+                    quote!(let #binding = #value;)
+                });
+                arms.push(quote! {
+                    #tag => {
+                        #(#reads)*
+                        ::std::result::Result::Ok(#name::#ident { #(#members: #bindings,)* })
+                    },
+                });
+            }
+            let deserialize = quote! {
+                *self = <Self as _serialize_rs::Decode>::decode_from(d)?;
+                ::std::result::Result::Ok(())
+            };
+            let decode = quote! {
+                let tag = d.read_tag(#width)?;
+                match tag {
+                    #(#arms)*
+                    tag => ::std::result::Result::Err(_serialize_rs::Error::UnknownTag { name: ::std::stringify!(#name), tag }),
+                }
+            };
+            let placeholder = match data.variants.first() {
+                Some(variant) => {
+                    let ident = &variant.ident;
+                    let fields = fields(&variant.fields)?;
+                    let members: Vec<&Member> = fields.iter().map(|field| &field.member).collect();
+                    let placeholders: Option<Vec<TokenStream2>> = fields.iter().map(placeholder_field).collect();
+                    placeholders.map(|placeholders| quote! {
+                        ::std::option::Option::Some(#name::#ident { #(#members: #placeholders,)* })
+                    })
+                },
+                None => None,
+            };
+            (deserialize, decode, placeholder)
+        },
+        Data::Union(_) => return Err(syn::Error::new(Span::call_site(), "unions can not derive DeSerialize")),
+    };
+    let deserialize = scoped(&container, quote!(d), deserialize);
+    let decode = scoped(&container, quote!(d), decode);
+    let placeholder = placeholder.map(|placeholder| quote! {
+        fn placeholder() -> ::std::option::Option<Self> {
+            #placeholder
+        }
+    });
+
+    // an enum's `DeSerialize` goes through `Decode` and needs its bounds
+    let generics = match input.data {
+        Data::Struct(_) => with_bound(&input.generics, &container, &all_fields, |bound| &bound.deserialize, |field| {
+            if field.attrs.skip || field.attrs.with.is_some() {
+                decode_bound(field)
+            } else {
+                Some(quote!(_serialize_rs::DeSerialize))
+            }
+        }),
+        _ => with_decode_bound(&input.generics, &container, &all_fields),
+    };
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let decode_generics = with_decode_bound(&input.generics, &container, &all_fields);
+    let (decode_impl_generics, _, decode_where_clause) = decode_generics.split_for_impl();
+
+    Ok(wrap(&container, quote! {
+        impl #impl_generics _serialize_rs::DeSerialize for #name #ty_generics #where_clause {
+            _serialize_rs::__dyn_method!(deserialize);
+
+            #[allow(unused_variables)]
+            fn deserialize_from<__R: ::std::io::Read>(&mut self, d: &mut _serialize_rs::Decoder<__R>) -> ::std::result::Result<(), _serialize_rs::Error> {
+                #deserialize
+            }
+        }
+
+        impl #decode_impl_generics _serialize_rs::Decode for #name #ty_generics #decode_where_clause {
+            _serialize_rs::__dyn_method!(decode);
+
+            #[allow(unused_variables)]
+            fn decode_from<__R: ::std::io::Read>(d: &mut _serialize_rs::Decoder<__R>) -> ::std::result::Result<Self, _serialize_rs::Error> {
+                #decode
+            }
+
+            #placeholder
+        }
+    }))
+}
@@ -0,0 +1,342 @@
+use serialize_rs::{Serialize, DeSerialize, Error, PathSegment, serialize_struct, deserialize_struct};
+use std::collections::HashMap;
+
+#[derive(Debug, Default, PartialEq, Serialize, DeSerialize)]
+struct Named {
+    a: i32,
+    b: String,
+    c: Option<Vec<u16>>,
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Listed {
+    a: i32,
+    b: String,
+    c: Option<Vec<u16>>,
+}
+
+serialize_struct!(Listed, a, b, c);
+deserialize_struct!(Listed, a, b, c);
+
+#[derive(Debug, Default, PartialEq, Serialize, DeSerialize)]
+struct Tuple(u8, String);
+
+#[derive(Debug, Default, PartialEq, Serialize, DeSerialize)]
+struct Unit;
+
+#[derive(Debug, Default, PartialEq, Serialize, DeSerialize)]
+enum Shape {
+    #[default]
+    Empty,
+    Circle(u32),
+    Rect { w: u32, h: u32 },
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, DeSerialize)]
+struct Wrapper<T> {
+    items: Vec<T>,
+    by_name: HashMap<String, T>,
+}
+
+#[test]
+fn test_derive_struct_matches_macros() {
+    let x = Named { a: -7, b: String::from("hello"), c: Some(vec![1, 2]) };
+    let y = Listed { a: -7, b: String::from("hello"), c: Some(vec![1, 2]) };
+    let bytes = serialize_rs::to_vec(&x).unwrap();
+    assert_eq!(bytes, serialize_rs::to_vec(&y).unwrap());
+    assert_eq!(serialize_rs::from_slice_exact::<Named>(&bytes).unwrap(), x);
+
+    let t = Tuple(3, String::from("ab"));
+    let bytes = serialize_rs::to_vec(&t).unwrap();
+    assert_eq!(bytes, vec![0x03u8, 0x00u8, 0x00u8, 0x00u8, 0x02u8, 0x61u8, 0x62u8]);
+    assert_eq!(serialize_rs::from_slice_exact::<Tuple>(&bytes).unwrap(), t);
+
+    assert_eq!(serialize_rs::to_vec(&Unit).unwrap().len(), 0);
+    assert_eq!(serialize_rs::from_slice_exact::<Unit>(&[]).unwrap(), Unit);
+
+    let mut w: Wrapper<Tuple> = Wrapper::default();
+    w.items.push(Tuple(1, String::from("x")));
+    w.by_name.insert(String::from("y"), Tuple(2, String::from("y")));
+    let bytes = serialize_rs::to_vec(&w).unwrap();
+    assert_eq!(serialize_rs::from_slice_exact::<Wrapper<Tuple>>(&bytes).unwrap(), w);
+}
+
+#[test]
+fn test_derive_enum() {
+    for shape in [Shape::Empty, Shape::Circle(5), Shape::Rect { w: 2, h: 3 }] {
+        let bytes = serialize_rs::to_vec(&shape).unwrap();
+        assert_eq!(serialize_rs::from_slice_exact::<Shape>(&bytes).unwrap(), shape);
+    }
+    assert_eq!(serialize_rs::to_vec(&Shape::Circle(5)).unwrap(),
+        vec![0x00u8, 0x00u8, 0x00u8, 0x01u8, 0x00u8, 0x00u8, 0x00u8, 0x05u8]);
+
+    let e = serialize_rs::from_slice::<Shape>(&[0x00u8, 0x00u8, 0x00u8, 0x03u8]).err().unwrap();
+    assert!(matches!(e.root(), Error::UnknownTag { name: "Shape", tag: 3 }));
+}
+
+#[test]
+fn test_derive_error_path() {
+    let t = Tuple(3, String::from("ab"));
+    let bytes = serialize_rs::to_vec(&t).unwrap();
+    let e = serialize_rs::from_slice::<Tuple>(&bytes[..5]).err().unwrap();
+    assert!(matches!(e.root(), Error::UnexpectedEof));
+    assert_eq!(e.path(), &[PathSegment::Field("1")]);
+
+    let bytes = serialize_rs::to_vec(&Shape::Rect { w: 2, h: 3 }).unwrap();
+    let e = serialize_rs::from_slice::<Shape>(&bytes[..9]).err().unwrap();
+    assert_eq!(e.path(), &[PathSegment::Field("h")]);
+}
+
+// a foreign-looking type with no serialize-rs impls
+#[derive(Debug, PartialEq)]
+struct Celsius(f32);
+
+mod celsius {
+    use super::Celsius;
+    use serialize_rs::{Decode, Decoder, Encoder, Error, Serialize};
+    use std::io::{Read, Write};
+
+    pub fn serialize_to<W: Write>(value: &Celsius, e: &mut Encoder<W>) -> Result<(), Error> {
+        ((value.0 * 10.0) as i16).serialize_to(e)
+    }
+
+    pub fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Celsius, Error> {
+        Ok(Celsius(i16::decode_from(d)? as f32 / 10.0))
+    }
+}
+
+fn unknown() -> String {
+    String::from("unknown")
+}
+
+#[derive(Debug, PartialEq, Serialize, DeSerialize)]
+struct Reading {
+    #[serialize(order = 1)]
+    id: u8,
+    #[serialize(with = "celsius", order = 0)]
+    temperature: Celsius,
+    #[serialize(skip)]
+    cache: Vec<u8>,
+    #[serialize(skip, default = "unknown")]
+    source: String,
+}
+
+impl Default for Reading {
+    fn default() -> Reading {
+        Reading { id: 0, temperature: Celsius(0.0), cache: vec![1], source: String::new() }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, DeSerialize)]
+enum Event {
+    Reading(#[serialize(with = "celsius")] Celsius, #[serialize(skip)] Vec<u8>),
+    Reset,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, DeSerialize)]
+#[serialize(endian = "little", length_width = "u8")]
+struct Compact {
+    a: u16,
+    b: Vec<u16>,
+}
+
+trait Codec {
+    type Wire;
+}
+
+impl Codec for u8 {
+    type Wire = u32;
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, DeSerialize)]
+#[serialize(bound(serialize = "C::Wire: serialize_rs::Serialize", deserialize = "C::Wire: serialize_rs::DeSerialize",
+    decode = "C::Wire: serialize_rs::Decode"))]
+struct Envelope<C: Codec> {
+    wire: C::Wire,
+}
+
+// no `Default`, so only the derived `Decode` can build it
+#[derive(Debug, PartialEq, Serialize, DeSerialize)]
+struct Port(#[serialize(with = "non_zero")] std::num::NonZeroU16);
+
+mod non_zero {
+    use serialize_rs::{Decode, Decoder, Encoder, Error, Serialize};
+    use std::io::{Read, Write};
+    use std::num::NonZeroU16;
+
+    pub fn serialize_to<W: Write>(value: &NonZeroU16, e: &mut Encoder<W>) -> Result<(), Error> {
+        value.get().serialize_to(e)
+    }
+
+    pub fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<NonZeroU16, Error> {
+        NonZeroU16::new(u16::decode_from(d)?).ok_or_else(|| Error::Custom(String::from("zero port")))
+    }
+}
+
+#[test]
+fn test_derive_field_attributes() {
+    let r = Reading { id: 9, temperature: Celsius(21.5), cache: vec![1, 2, 3], source: String::from("probe") };
+    let bytes = serialize_rs::to_vec(&r).unwrap();
+    assert_eq!(bytes, vec![0x00u8, 0xd7u8, 0x09u8]);
+
+    let decoded: Reading = serialize_rs::from_slice_exact(&bytes).unwrap();
+    assert_eq!(decoded, Reading { id: 9, temperature: Celsius(21.5), cache: Vec::new(), source: String::from("unknown") });
+
+    let e = serialize_rs::from_slice::<Reading>(&bytes[..1]).err().unwrap();
+    assert_eq!(e.path(), &[PathSegment::Field("temperature")]);
+
+    let ports = vec![Port(std::num::NonZeroU16::new(80).unwrap()), Port(std::num::NonZeroU16::new(443).unwrap())];
+    let bytes = serialize_rs::to_vec(&ports).unwrap();
+    assert_eq!(serialize_rs::from_slice_exact::<Vec<Port>>(&bytes).unwrap(), ports);
+    let by_id: std::collections::BTreeMap<u8, Port> = ports.into_iter().enumerate().map(|(i, port)| (i as u8, port)).collect();
+    assert_eq!(serialize_rs::to_vec(&by_id).unwrap().len(), 10);
+    let e = serialize_rs::from_slice::<Port>(&[0x00u8, 0x00u8]).err().unwrap();
+    assert_eq!(e.path(), &[PathSegment::Field("0")]);
+
+    let bytes = serialize_rs::to_vec(&Event::Reading(Celsius(-1.0), vec![7])).unwrap();
+    assert_eq!(bytes, vec![0x00u8, 0x00u8, 0x00u8, 0x00u8, 0xffu8, 0xf6u8]);
+    let mut event = Event::Reset;
+    event.deserialize(&mut bytes.as_slice()).unwrap();
+    assert_eq!(event, Event::Reading(Celsius(-1.0), Vec::new()));
+}
+
+#[test]
+fn test_derive_container_attributes() {
+    let c = Compact { a: 1, b: vec![2] };
+    let bytes = serialize_rs::to_vec(&c).unwrap();
+    assert_eq!(bytes, vec![0x01u8, 0x00u8, 0x01u8, 0x02u8, 0x00u8]);
+    assert_eq!(serialize_rs::from_slice_exact::<Compact>(&bytes).unwrap(), c);
+
+    // the override ends with the value
+    let bytes = serialize_rs::to_vec(&vec![Compact { a: 1, b: Vec::new() }]).unwrap();
+    assert_eq!(bytes, vec![0x00u8, 0x00u8, 0x00u8, 0x01u8, 0x01u8, 0x00u8, 0x00u8]);
+
+    let x: Envelope<u8> = Envelope { wire: 5 };
+    let bytes = serialize_rs::to_vec(&x).unwrap();
+    assert_eq!(bytes, vec![0x00u8, 0x00u8, 0x00u8, 0x05u8]);
+    assert_eq!(serialize_rs::from_slice_exact::<Envelope<u8>>(&bytes).unwrap(), x);
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, DeSerialize)]
+#[serialize(tag_width = "u8")]
+#[repr(u8)]
+enum Command {
+    #[default]
+    Nop = 1,
+    Move { dx: i16, dy: i16 },
+    #[serialize(tag = 0x80)]
+    Say(String),
+}
+
+#[test]
+fn test_derive_enum_tags() {
+    // the discriminant of `Nop` doesn't change the tags
+    assert_eq!(serialize_rs::to_vec(&Command::Nop).unwrap(), vec![0x00u8]);
+    assert_eq!(serialize_rs::to_vec(&Command::Move { dx: 1, dy: -1 }).unwrap(),
+        vec![0x01u8, 0x00u8, 0x01u8, 0xffu8, 0xffu8]);
+    assert_eq!(serialize_rs::to_vec(&Command::Say(String::new())).unwrap(),
+        vec![0x80u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8]);
+    for command in [Command::Nop, Command::Move { dx: 1, dy: -1 }, Command::Say(String::from("hi"))] {
+        let bytes = serialize_rs::to_vec(&command).unwrap();
+        assert_eq!(serialize_rs::from_slice_exact::<Command>(&bytes).unwrap(), command);
+    }
+
+    let e = serialize_rs::from_slice::<Command>(&[0x02u8]).err().unwrap();
+    assert_eq!(e.to_string(), "unknown tag 2 for `Command` at byte 1");
+}
+
+// names the expansion must not pick up from the caller's scope
+mod shadowed {
+    use serialize_rs::{Serialize, DeSerialize};
+
+    #[allow(dead_code)]
+    pub type Result<T> = std::result::Result<T, String>;
+
+    #[allow(dead_code)]
+    pub enum Reply { Ok, Err, Some }
+    #[allow(unused_imports)]
+    pub use Reply::*;
+
+    #[allow(unused_macros)]
+    macro_rules! stringify {
+        ($($t:tt)*) => { 0 };
+    }
+
+    #[derive(Debug, PartialEq, Serialize, DeSerialize)]
+    pub struct Frame<R, W> {
+        pub read: R,
+        pub written: W,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, DeSerialize)]
+    pub enum Packet {
+        Data { d: u8, e: u8 },
+    }
+}
+
+#[test]
+fn test_derive_hygiene() {
+    use shadowed::{Frame, Packet};
+
+    let frame = Frame { read: 1u8, written: String::from("w") };
+    let bytes = serialize_rs::to_vec(&frame).unwrap();
+    assert_eq!(serialize_rs::from_slice_exact::<Frame<u8, String>>(&bytes).unwrap(), frame);
+
+    let packet = Packet::Data { d: 1, e: 2 };
+    let bytes = serialize_rs::to_vec(&packet).unwrap();
+    assert_eq!(bytes, vec![0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x01u8, 0x02u8]);
+    assert_eq!(serialize_rs::from_slice_exact::<Packet>(&bytes).unwrap(), packet);
+    let e = serialize_rs::from_slice::<Packet>(&[0x00u8, 0x00u8, 0x00u8, 0x01u8]).err().unwrap();
+    assert_eq!(e.to_string(), "unknown tag 1 for `Packet` at byte 4");
+}
+
+// a hand-written impl with no `Decode` impl
+#[derive(Debug, Default, PartialEq)]
+struct Legacy(u8);
+
+impl Serialize for Legacy {
+    fn serialize(&self, w: &mut dyn std::io::Write) -> Result<(), Error> {
+        self.0.serialize(w)
+    }
+}
+
+impl DeSerialize for Legacy {
+    fn deserialize(&mut self, r: &mut dyn std::io::BufRead) -> Result<(), Error> {
+        self.0.deserialize(r)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Serialize, DeSerialize)]
+struct Holder {
+    legacy: Legacy,
+    n: u8,
+}
+
+#[test]
+fn test_derive_deserialize_only_field() {
+    let holder = Holder { legacy: Legacy(4), n: 5 };
+    let bytes = serialize_rs::to_vec(&holder).unwrap();
+    assert_eq!(bytes, vec![0x04u8, 0x05u8]);
+    let mut value = Holder::default();
+    value.deserialize(&mut bytes.as_slice()).unwrap();
+    assert_eq!(value, holder);
+}
+
+mod renamed {
+    pub use serialize_rs as ser;
+}
+
+#[derive(Debug, PartialEq, renamed::ser::Serialize, renamed::ser::DeSerialize)]
+#[serialize(crate = "renamed::ser")]
+struct Relocated {
+    a: u8,
+    b: Option<u16>,
+}
+
+#[test]
+fn test_derive_crate_path() {
+    let value = Relocated { a: 1, b: Some(2) };
+    let bytes = serialize_rs::to_vec(&value).unwrap();
+    assert_eq!(bytes, vec![0x01u8, 0x01u8, 0x00u8, 0x02u8]);
+    assert_eq!(serialize_rs::from_slice_exact::<Relocated>(&bytes).unwrap(), value);
+}
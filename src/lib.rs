@@ -1,641 +1,2959 @@
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::collections::{VecDeque, LinkedList, HashMap, BTreeMap, HashSet, BTreeSet, BinaryHeap};
-use std::io::{BufRead, Write};
-use std::error::Error;
+use std::io::{self, BufRead, Read, Write};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::thread::LocalKey;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::convert::TryFrom;
+use std::error;
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fmt;
 use std::hash::Hash;
+use std::marker::PhantomData;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::{Path, PathBuf};
+
+#[cfg(feature = "derive")]
+pub use serialize_rs_derive::{Serialize, DeSerialize};
+
+/// Errors returned while serializing or deserializing a value.
+#[derive(Debug)]
+pub enum Error {
+    /// The stream ended before the value was complete.
+    UnexpectedEof,
+    /// A `bool` was encoded as something other than 0 or 1.
+    InvalidBool(u8),
+    /// A string payload is not valid UTF-8.
+    InvalidUtf8,
+    /// A `CString` payload with a nul byte at the given position.
+    InteriorNul(usize),
+    /// A length does not fit into the length prefix.
+    LengthOverflow(u64),
+    /// An integer that doesn't fit into `target` on this host, e.g. a `usize`
+    /// written on a 64-bit machine and read on a 32-bit one.
+    IntegerOverflow { value: i128, target: &'static str },
+    /// A `Duration` or `SystemTime` with a nanosecond part of a second or more.
+    InvalidNanos(u32),
+    /// An enum tag that matches none of the variants of `name`.
+    UnknownTag { name: &'static str, tag: u32 },
+    /// A varint is overlong, not minimally encoded or too large for its type.
+    InvalidVarint,
+    /// A configured decoding limit was exceeded.
+    LimitExceeded,
+    /// Values were nested deeper than the configured maximum depth.
+    DepthExceeded(usize),
+    /// A graph-mode reference to an object that doesn't exist, has another
+    /// type or can't be taken yet (a strong cycle).
+    InvalidReference(u64),
+    /// Bytes were left over after decoding a complete value.
+    TrailingBytes(u64),
+    /// Any other I/O failure of the underlying reader or writer.
+    Io(io::Error),
+    /// A free-form error raised by a user implementation.
+    Custom(String),
+    /// A decoding error together with the byte offset and the field path at
+    /// which it happened.
+    Located { offset: u64, path: Vec<PathSegment>, error: Box<Error> },
+}
+
+impl Error {
+    /// Strips any location information and returns the underlying error.
+    pub fn root(&self) -> &Error {
+        match self {
+            Error::Located { error, .. } => error.root(),
+            e => e,
+        }
+    }
+
+    /// Byte offset at which decoding failed: the number of bytes consumed
+    /// when the error was detected, so one past a bad byte rather than the
+    /// start of the value it belonged to. It counts from where the outermost
+    /// `Decoder` started; a hand-written `deserialize` called directly on a
+    /// `BufRead` has none above it, and its fields count from their own start.
+    pub fn offset(&self) -> Option<u64> {
+        match self {
+            Error::Located { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+
+    /// Path from the outermost value down to the one that failed.
+    pub fn path(&self) -> &[PathSegment] {
+        match self {
+            Error::Located { path, .. } => path,
+            _ => &[],
+        }
+    }
+
+    // The offset is overwritten on every level: the outermost decoder has seen
+    // every byte, so its position is the absolute one.
+    fn located(self, offset: u64, segment: Option<PathSegment>) -> Error {
+        match self {
+            Error::Located { mut path, error, .. } => {
+                if let Some(segment) = segment {
+                    path.insert(0, segment);
+                }
+                Error::Located { offset, path, error }
+            },
+            e => Error::Located { offset, path: segment.into_iter().collect(), error: Box::new(e) },
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UnexpectedEof => write!(f, "unexpected end of stream"),
+            Error::InvalidBool(v) => write!(f, "invalid bool value: {:#04x}", v),
+            Error::InvalidUtf8 => write!(f, "invalid utf-8 string"),
+            Error::InteriorNul(pos) => write!(f, "nul byte at position {} of a c string", pos),
+            Error::LengthOverflow(len) => write!(f, "length {} does not fit into the length prefix", len),
+            Error::IntegerOverflow { value, target } => write!(f, "{} does not fit into `{}`", value, target),
+            Error::InvalidNanos(nanos) => write!(f, "{} nanoseconds out of range", nanos),
+            Error::UnknownTag { name, tag } => write!(f, "unknown tag {} for `{}`", tag, name),
+            Error::InvalidVarint => write!(f, "invalid varint"),
+            Error::LimitExceeded => write!(f, "decoding limit exceeded"),
+            Error::DepthExceeded(max) => write!(f, "values nested deeper than {} levels", max),
+            Error::InvalidReference(id) => write!(f, "invalid shared reference {}", id),
+            Error::TrailingBytes(n) => write!(f, "{} trailing bytes after the decoded value", n),
+            Error::Io(e) => write!(f, "{}", e),
+            Error::Custom(msg) => write!(f, "{}", msg),
+            Error::Located { offset, path, error } => {
+                write!(f, "{} at byte {}", error, offset)?;
+                if !path.is_empty() {
+                    write!(f, " in `")?;
+                    for (i, segment) in path.iter().enumerate() {
+                        if i != 0 {
+                            if let PathSegment::Field(_) = segment {
+                                write!(f, ".")?;
+                            }
+                        }
+                        write!(f, "{}", segment)?;
+                    }
+                    write!(f, "`")?;
+                }
+                Ok(())
+            },
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Located { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        if e.kind() == io::ErrorKind::UnexpectedEof {
+            Error::UnexpectedEof
+        } else if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            // one of ours, passed up through the `Read` impl of `Decoder`
+            *e.into_inner().unwrap().downcast::<Error>().unwrap()
+        } else {
+            Error::Io(e)
+        }
+    }
+}
+
+impl From<&str> for Error {
+    fn from(msg: &str) -> Error {
+        Error::Custom(String::from(msg))
+    }
+}
+
+impl From<String> for Error {
+    fn from(msg: String) -> Error {
+        Error::Custom(msg)
+    }
+}
+
+/// Implementors provide `serialize`, which `serialize_to` goes through by
+/// default. Overriding `serialize_to` as well gives the statically
+/// dispatched path a chance to inline the writes.
+///
+/// The generic methods require `Self: Sized`, which keeps the trait usable as
+/// `dyn Serialize`. Unsized types such as `str` are only written through
+/// `serialize`.
+pub trait Serialize {
+    fn serialize(&self, w: &mut dyn Write) -> Result<(), Error>;
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error>
+        where Self: Sized {
+        e.serialize_unsized(self)
+    }
+
+    /// Writes the elements of a slice or array back to back. Overridden by
+    /// `u8` to write the bytes in one go.
+    fn serialize_slice_to<W: Write>(values: &[Self], e: &mut Encoder<W>) -> Result<(), Error>
+        where Self: Sized {
+        for v in values.iter() {
+            v.serialize_to(e)?;
+        }
+        Ok(())
+    }
+}
+
+/// Implementors provide `deserialize`, which `deserialize_from` goes through
+/// by default. Overriding `deserialize_from` as well and forwarding the
+/// `Decoder` to every field keeps error paths complete.
+///
+/// As with `Serialize`, the generic methods require `Self: Sized`.
+pub trait DeSerialize {
+    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Error>;
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error>
+        where Self: Sized {
+        d.bridged(|d| self.deserialize(d))
+    }
+
+    /// Fills every element of `values` in order. Overridden by `u8` to read
+    /// the bytes in one go.
+    fn deserialize_slice_from<R: Read>(values: &mut [Self], d: &mut Decoder<R>) -> Result<(), Error>
+        where Self: Sized {
+        for (i, v) in values.iter_mut().enumerate() {
+            d.nested(PathSegment::Index(i), |d| v.deserialize_from(d))?;
+        }
+        Ok(())
+    }
+}
+
+/// Builds a fresh value from the stream instead of filling an existing one.
+///
+/// Built-in types implement it directly. `deserialize_struct!` implements it
+/// for structs with a `Default` value, `#[derive(DeSerialize)]` field by
+/// field, and a hand-written `DeSerialize + Default` type opts in with
+/// `decode_default!`. Types without a sensible default implement it by hand. As with
+/// `DeSerialize`, implementors provide `decode` and may override `decode_from`.
+pub trait Decode: Sized {
+    fn decode(r: &mut dyn BufRead) -> Result<Self, Error>;
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        d.bridged(|d| Self::decode(d))
+    }
+
+    /// A throwaway value to finish a graph-mode `Rc`/`Arc` with when decoding
+    /// its contents fails half-way. Without one, a `Weak` inside the value
+    /// can't point back at the object being decoded.
+    fn placeholder() -> Option<Self> {
+        None
+    }
+}
+
+/// One step of the path reported by `Error::Located`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathSegment {
+    /// A named (or tuple-indexed) struct field.
+    Field(&'static str),
+    /// A position in a sequence, set or map, counted in stream order. Map
+    /// keys are not recorded; for a `HashMap` the position follows the
+    /// writer's iteration order, so the offset is the better guide.
+    Index(usize),
+}
+
+impl fmt::Display for PathSegment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathSegment::Field(name) => write!(f, "{}", name),
+            PathSegment::Index(i) => write!(f, "[{}]", i),
+        }
+    }
+}
+
+/// Byte order of multi-byte integers, floats and length prefixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endian {
+    Big,
+    Little,
+}
+
+/// Width of the element count written in front of strings and collections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LengthWidth {
+    U8,
+    U16,
+    U32,
+    /// For strings and collections larger than 4 GiB.
+    U64,
+    /// LEB128, one byte for lengths below 128.
+    Varint,
+}
+
+/// Width of the variant tag written in front of an enum value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagWidth {
+    U8,
+    U16,
+    U32,
+    Varint,
+}
+
+/// How integers wider than a byte are written.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntEncoding {
+    /// Always `size_of::<T>()` bytes in the configured byte order.
+    Fixed,
+    /// LEB128 for unsigned integers, zigzag followed by LEB128 for signed
+    /// ones. `u8`/`i8` and floats stay fixed.
+    Varint,
+}
+
+/// How `String` and `char` payloads are interpreted when decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringEncoding {
+    /// UTF-8 bytes, validated on decode. This is what `Serialize` writes.
+    Utf8,
+    /// One byte per character, as written by releases before strings were
+    /// UTF-8. Only meant for reading old data.
+    Latin1,
+}
+
+/// Bounds on what a `Decoder` may consume or allocate, for reading untrusted
+/// input. Going over one fails with `Error::LimitExceeded`, or
+/// `Error::DepthExceeded` for the nesting depth. Every limit but the depth is
+/// off by default; the depth is capped at `DEFAULT_MAX_DEPTH` so that a
+/// recursive type can't overflow the stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    max_bytes: Option<u64>,
+    max_alloc: Option<usize>,
+    max_length: Option<usize>,
+    max_depth: Option<usize>,
+}
+
+/// Nesting depth allowed by `Limits::default()`. Only what a type can
+/// recurse through counts: the payload of an `Option` or `Result`, the
+/// pointee of a `Box`, `Rc` or `Arc`, and the elements of a collection.
+pub const DEFAULT_MAX_DEPTH: usize = 128;
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits { max_bytes: None, max_alloc: None, max_length: None, max_depth: Some(DEFAULT_MAX_DEPTH) }
+    }
+}
+
+impl Limits {
+    pub fn new() -> Limits {
+        Limits::default()
+    }
+
+    /// Total number of bytes read from the stream.
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Limits {
+        self.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Bytes a single string or collection may claim from its length prefix.
+    /// Zero-sized elements count as one byte each.
+    pub fn with_max_alloc(mut self, max_alloc: usize) -> Limits {
+        self.max_alloc = Some(max_alloc);
+        self
+    }
+
+    /// Element count of any string or collection.
+    pub fn with_max_length(mut self, max_length: usize) -> Limits {
+        self.max_length = Some(max_length);
+        self
+    }
+
+    /// Nesting of elements, `Some` and `Ok`/`Err` payloads and pointees;
+    /// struct fields don't count. Pass `usize::MAX` to lift the default cap.
+    pub fn with_max_depth(mut self, max_depth: usize) -> Limits {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    pub fn max_bytes(&self) -> Option<u64> {
+        self.max_bytes
+    }
+
+    pub fn max_alloc(&self) -> Option<usize> {
+        self.max_alloc
+    }
+
+    pub fn max_length(&self) -> Option<usize> {
+        self.max_length
+    }
+
+    pub fn max_depth(&self) -> Option<usize> {
+        self.max_depth
+    }
+}
+
+/// Encoding options carried by an `Encoder` or `Decoder` and seen by every
+/// value below it.
+///
+/// `Config::default()` is the crate's original format: big-endian fixed-width
+/// numbers and `u32` length prefixes. Hand-written impls that only provide the
+/// dyn-based `serialize`/`deserialize` pass the config, the nesting depth and
+/// the graph-mode sharing on to their fields through
+/// `Encoder::inherit`/`Decoder::inherit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    endian: Endian,
+    length_width: LengthWidth,
+    int_encoding: IntEncoding,
+    string_encoding: StringEncoding,
+    limits: Limits,
+    graph: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            endian: Endian::Big,
+            length_width: LengthWidth::U32,
+            int_encoding: IntEncoding::Fixed,
+            string_encoding: StringEncoding::Utf8,
+            limits: Limits::default(),
+            graph: false,
+        }
+    }
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config::default()
+    }
+
+    pub fn with_endian(mut self, endian: Endian) -> Config {
+        self.endian = endian;
+        self
+    }
+
+    pub fn with_length_width(mut self, length_width: LengthWidth) -> Config {
+        self.length_width = length_width;
+        self
+    }
+
+    pub fn with_int_encoding(mut self, int_encoding: IntEncoding) -> Config {
+        self.int_encoding = int_encoding;
+        self
+    }
+
+    pub fn with_string_encoding(mut self, string_encoding: StringEncoding) -> Config {
+        self.string_encoding = string_encoding;
+        self
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Config {
+        self.limits = limits;
+        self
+    }
+
+    /// Writes every `Rc`/`Arc` once and later occurrences of the same
+    /// pointer as back-references, so that decoding restores the sharing.
+    pub fn with_graph(mut self, graph: bool) -> Config {
+        self.graph = graph;
+        self
+    }
+
+    pub fn endian(&self) -> Endian {
+        self.endian
+    }
+
+    pub fn length_width(&self) -> LengthWidth {
+        self.length_width
+    }
+
+    pub fn int_encoding(&self) -> IntEncoding {
+        self.int_encoding
+    }
+
+    pub fn string_encoding(&self) -> StringEncoding {
+        self.string_encoding
+    }
+
+    pub fn limits(&self) -> Limits {
+        self.limits
+    }
+
+    pub fn graph(&self) -> bool {
+        self.graph
+    }
+
+    /// Serializes `value` into a new byte vector.
+    pub fn to_vec<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+        let mut e = Encoder::with_config(Vec::new(), *self);
+        e.serialize(value)?;
+        Ok(e.into_inner())
+    }
+
+    /// Serializes `value` into `writer`.
+    pub fn to_writer<W: Write, T: Serialize>(&self, writer: &mut W, value: &T) -> Result<(), Error> {
+        Encoder::with_config(writer, *self).serialize(value)
+    }
+
+    /// Decodes a value from the start of `bytes`, ignoring anything after it.
+    pub fn from_slice<T: Decode>(&self, bytes: &[u8]) -> Result<T, Error> {
+        Decoder::with_config(bytes, *self).decode()
+    }
+
+    /// Decodes a value from `bytes`, failing if any bytes are left over.
+    pub fn from_slice_exact<T: Decode>(&self, bytes: &[u8]) -> Result<T, Error> {
+        let mut d = Decoder::with_config(bytes, *self);
+        let t = d.decode()?;
+        let rest = bytes.len() as u64 - d.offset();
+        if rest != 0 {
+            return Err(Error::TrailingBytes(rest).located(d.offset(), None));
+        }
+        Ok(t)
+    }
+
+    /// Decodes a value from `reader`, reading no further than the value itself.
+    pub fn from_reader<T: Decode, R: Read>(&self, reader: R) -> Result<T, Error> {
+        Decoder::with_config(reader, *self).decode()
+    }
+}
+
+thread_local! {
+    // State of the innermost `Encoder`/`Decoder` that handed a value over to
+    // its dyn-based method, for `inherit` to borrow.
+    static ENCODER_STATE: RefCell<Option<EncoderState>> = const { RefCell::new(None) };
+    static DECODER_STATE: RefCell<Option<DecoderState>> = const { RefCell::new(None) };
+}
+
+struct EncoderState {
+    config: Config,
+    shared: HashMap<usize, u64>,
+}
+
+struct DecoderState {
+    config: Config,
+    offset: u64,
+    depth: usize,
+    shared: Vec<SharedSlot>,
+}
+
+// Puts the state of a coder in place for the duration of a dyn-based call.
+// `finish` takes it back along with what `inherit` added to it; if the call
+// panics instead, dropping the bridge still restores what it replaced.
+struct Bridge<S: 'static> {
+    key: &'static LocalKey<RefCell<Option<S>>>,
+    outer: Option<Option<S>>,
+}
+
+impl<S: 'static> Bridge<S> {
+    fn new(key: &'static LocalKey<RefCell<Option<S>>>, state: S) -> Bridge<S> {
+        Bridge { key, outer: Some(key.with(|cell| cell.replace(Some(state)))) }
+    }
+
+    fn finish(mut self) -> Option<S> {
+        let outer = self.outer.take().flatten();
+        self.key.with(|cell| cell.replace(outer))
+    }
+}
+
+impl<S: 'static> Drop for Bridge<S> {
+    fn drop(&mut self) {
+        if let Some(outer) = self.outer.take() {
+            self.key.with(|cell| cell.replace(outer));
+        }
+    }
+}
+
+/// Writer handed down through `Serialize::serialize_to`.
+///
+/// `Encoder<&mut dyn Write>` backs the dyn-based `serialize`; any other `W`
+/// is statically dispatched. Both produce the same bytes.
+pub struct Encoder<W> {
+    writer: W,
+    config: Config,
+    // graph mode: object id of every shared pointer written so far, by address
+    shared: HashMap<usize, u64>,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(writer: W) -> Encoder<W> {
+        Encoder::with_config(writer, Config::default())
+    }
+
+    pub fn with_config(writer: W, config: Config) -> Encoder<W> {
+        Encoder { writer, config, shared: HashMap::new() }
+    }
+
+    /// Runs `f` on an encoder over `writer` that carries on from the encoder
+    /// that called the dyn-based `serialize` this runs in, if any: it has the
+    /// same config and, in graph mode, knows the shared pointers written so
+    /// far. The dyn-based methods of the built-in impls start from here.
+    pub fn inherit<T, F>(writer: W, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Encoder<W>) -> Result<T, Error> {
+        match ENCODER_STATE.with(|cell| cell.borrow_mut().take()) {
+            Some(EncoderState { config, shared }) => {
+                let mut e = Encoder { writer, config, shared };
+                let r = f(&mut e);
+                ENCODER_STATE.with(|cell| cell.replace(Some(EncoderState { config, shared: e.shared })));
+                r
+            },
+            None => f(&mut Encoder::new(writer)),
+        }
+    }
+
+    // Lends this encoder's state to `inherit` while `f` calls a dyn-based
+    // `serialize` with it.
+    fn bridged<T, F>(&mut self, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Encoder<W>) -> Result<T, Error> {
+        let state = EncoderState { config: self.config, shared: std::mem::take(&mut self.shared) };
+        let bridge = Bridge::new(&ENCODER_STATE, state);
+        let r = f(self);
+        if let Some(state) = bridge.finish() {
+            self.shared = state.shared;
+        }
+        r
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Runs `f` with `config` in place of the current config, e.g. to write
+    /// one value in another byte order.
+    pub fn scoped<T, F>(&mut self, config: Config, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Encoder<W>) -> Result<T, Error> {
+        let outer = std::mem::replace(&mut self.config, config);
+        let r = f(self);
+        self.config = outer;
+        r
+    }
+
+    /// Serializes `value` into the underlying writer.
+    pub fn serialize<T: Serialize>(&mut self, value: &T) -> Result<(), Error> {
+        value.serialize_to(self)
+    }
+
+    /// Serializes a value that may be unsized, such as `str` or
+    /// `dyn Serialize`, through its dyn-based `serialize`.
+    pub fn serialize_unsized<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), Error> {
+        self.bridged(|e| value.serialize(e))
+    }
+
+    /// Writes a string or collection length using the configured prefix.
+    pub fn write_length(&mut self, length: usize) -> Result<(), Error> {
+        let overflow = |_| Error::LengthOverflow(length as u64);
+        match self.config.length_width {
+            LengthWidth::U8 => u8::try_from(length).map_err(overflow)?.serialize_to(self),
+            LengthWidth::U16 => u16::try_from(length).map_err(overflow)?.serialize_to(self),
+            LengthWidth::U32 => u32::try_from(length).map_err(overflow)?.serialize_to(self),
+            LengthWidth::U64 => (length as u64).serialize_to(self),
+            LengthWidth::Varint => self.write_varint(length as u128),
+        }
+    }
+
+    /// Writes an enum tag. `U16` and `U32` tags follow the int encoding like
+    /// any other integer.
+    pub fn write_tag(&mut self, width: TagWidth, tag: u32) -> Result<(), Error> {
+        let overflow = |_| Error::Custom(format!("tag {} does not fit into a {:?} tag", tag, width));
+        match width {
+            TagWidth::U8 => u8::try_from(tag).map_err(overflow)?.serialize_to(self),
+            TagWidth::U16 => u16::try_from(tag).map_err(overflow)?.serialize_to(self),
+            TagWidth::U32 => tag.serialize_to(self),
+            TagWidth::Varint => self.write_varint(tag as u128),
+        }
+    }
+
+    /// Writes `value` as LEB128: seven bits per byte, least significant group
+    /// first, with the high bit set on every byte but the last.
+    pub fn write_varint(&mut self, mut value: u128) -> Result<(), Error> {
+        let mut buffer = [0x0u8; 19];
+        let mut n = 0;
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                buffer[n] = byte;
+                n += 1;
+                break;
+            }
+            buffer[n] = byte | 0x80;
+            n += 1;
+        }
+        self.write_all(&buffer[..n])?;
+        Ok(())
+    }
+
+    /// Writes the graph-mode reference to the shared object at `address`.
+    /// Returns true the first time the object is seen, in which case the
+    /// caller writes its value next.
+    pub fn write_shared(&mut self, address: usize) -> Result<bool, Error> {
+        if let Some(&id) = self.shared.get(&address) {
+            self.write_varint(id as u128)?;
+            return Ok(false);
+        }
+        let id = FIRST_SHARED_ID + self.shared.len() as u64;
+        self.shared.insert(address, id);
+        self.write_varint(NEW_SHARED as u128)?;
+        Ok(true)
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer.write(buf)
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.writer.write_all(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+// Graph mode writes a varint in front of every shared pointer: 0 for a
+// dangling `Weak`, 1 for an object whose value follows and which gets the
+// next id, and the id itself for an object written before.
+const NULL_SHARED: u64 = 0;
+const NEW_SHARED: u64 = 1;
+const FIRST_SHARED_ID: u64 = 2;
+
+/// A graph-mode reference read by `Decoder::read_shared`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SharedRef {
+    /// A dangling `Weak`.
+    Null,
+    /// The first occurrence of object `id`; its value follows.
+    New(u64),
+    /// Object `id`, decoded earlier (or still being decoded).
+    Back(u64),
+}
+
+// Decoded pointers are kept alive until the decoder is dropped, so that a
+// `Weak` can point at an object whose only owner comes later in the stream.
+struct SharedSlot {
+    strong: Option<Box<dyn Any>>,
+    weak: Option<Box<dyn Any>>,
+}
+
+/// Reader handed down through `DeSerialize::deserialize_from`.
+///
+/// It only needs `Read` from the underlying reader, counts the bytes consumed
+/// so far and lets containers attach their part of the path to errors raised
+/// below them. `Decoder<&mut dyn BufRead>` backs the dyn-based `deserialize`.
+pub struct Decoder<R> {
+    reader: R,
+    config: Config,
+    offset: u64,
+    depth: usize,
+    // bytes pulled in by `fill_buf` for hand-written impls that use `BufRead`
+    peeked: Vec<u8>,
+    pos: usize,
+    // graph mode: objects decoded so far, indexed by id - FIRST_SHARED_ID
+    shared: Vec<SharedSlot>,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(reader: R) -> Decoder<R> {
+        Decoder::with_config(reader, Config::default())
+    }
+
+    pub fn with_config(reader: R, config: Config) -> Decoder<R> {
+        Decoder { reader, config, offset: 0, depth: 0, peeked: Vec::new(), pos: 0, shared: Vec::new() }
+    }
+
+    /// Runs `f` on a decoder over `reader` that carries on from the decoder
+    /// that called the dyn-based `deserialize` or `decode` this runs in, if
+    /// any: it has the same config and nesting depth, so that a recursive
+    /// hand-written impl stays within `Limits::max_depth`, counts offsets from
+    /// the same start, and in graph mode knows the shared objects decoded so
+    /// far. The dyn-based methods of
+    /// the built-in impls start from here.
+    pub fn inherit<T, F>(reader: R, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Decoder<R>) -> Result<T, Error> {
+        match DECODER_STATE.with(|cell| cell.borrow_mut().take()) {
+            Some(DecoderState { config, offset, depth, shared }) => {
+                let mut d = Decoder { offset, depth, shared, ..Decoder::with_config(reader, config) };
+                let r = f(&mut d);
+                // the next field of a hand-written impl starts where `d`
+                // stopped; bytes it reads from `reader` directly are not
+                // counted until the outer decoder takes over again
+                let state = DecoderState { config, offset: d.offset, depth, shared: d.shared };
+                DECODER_STATE.with(|cell| cell.replace(Some(state)));
+                r
+            },
+            None => f(&mut Decoder::new(reader)),
+        }
+    }
+
+    // Lends this decoder's state to `inherit` while `f` calls a dyn-based
+    // `deserialize` or `decode` with it.
+    fn bridged<T, F>(&mut self, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Decoder<R>) -> Result<T, Error> {
+        let state = DecoderState {
+            config: self.config,
+            offset: self.offset,
+            depth: self.depth,
+            shared: std::mem::take(&mut self.shared),
+        };
+        let bridge = Bridge::new(&DECODER_STATE, state);
+        let r = f(self);
+        if let Some(state) = bridge.finish() {
+            self.shared = state.shared;
+        }
+        r
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Number of bytes consumed, counted from where the outermost decoder
+    /// started when this one comes from `inherit`.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Runs `f` with `config` in place of the current config. Limits keep
+    /// applying to the decoder as a whole, whatever `config` says.
+    pub fn scoped<T, F>(&mut self, config: Config, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Decoder<R>) -> Result<T, Error> {
+        let config = config.with_limits(self.config.limits);
+        let outer = std::mem::replace(&mut self.config, config);
+        let r = f(self);
+        self.config = outer;
+        r
+    }
+
+    /// Fills `value` from the stream; errors carry the offset at which they
+    /// happened.
+    pub fn deserialize<T: DeSerialize>(&mut self, value: &mut T) -> Result<(), Error> {
+        let r = value.deserialize_from(self);
+        r.map_err(|e| e.located(self.offset, None))
+    }
+
+    /// Builds a fresh value from the stream; errors carry the offset at which
+    /// they happened.
+    pub fn decode<T: Decode>(&mut self) -> Result<T, Error> {
+        let r = T::decode_from(self);
+        r.map_err(|e| e.located(self.offset, None))
+    }
+
+    /// Reads a string or collection length written by `Encoder::write_length`.
+    pub fn read_length(&mut self) -> Result<usize, Error> {
+        let length = match self.config.length_width {
+            LengthWidth::U8 => u8::decode_from(self)? as u64,
+            LengthWidth::U16 => u16::decode_from(self)? as u64,
+            LengthWidth::U32 => u32::decode_from(self)? as u64,
+            LengthWidth::U64 => u64::decode_from(self)?,
+            LengthWidth::Varint => self.read_varint(64)? as u64,
+        };
+        // a length written on a 64-bit host may not fit a 32-bit one
+        let length = usize::try_from(length).map_err(|_| Error::LengthOverflow(length))?;
+        if self.config.limits.max_length.is_some_and(|max| length > max) {
+            return Err(Error::LimitExceeded);
+        }
+        Ok(length)
+    }
+
+    /// Fails if `count` values of `T` would take more than the configured
+    /// `Limits::max_alloc` bytes. A zero-sized `T` counts as one byte, so a
+    /// huge prefix on a `Vec<()>` can't loop through billions of elements
+    /// that consume no input.
+    pub fn check_alloc<T>(&self, count: usize) -> Result<(), Error> {
+        let bytes = count.saturating_mul(std::mem::size_of::<T>().max(1));
+        if self.config.limits.max_alloc.is_some_and(|max| bytes > max) {
+            return Err(Error::LimitExceeded);
+        }
+        Ok(())
+    }
+
+    /// Reads an enum tag written by `Encoder::write_tag`.
+    pub fn read_tag(&mut self, width: TagWidth) -> Result<u32, Error> {
+        let tag = match width {
+            TagWidth::U8 => u8::decode_from(self)? as u32,
+            TagWidth::U16 => u16::decode_from(self)? as u32,
+            TagWidth::U32 => u32::decode_from(self)?,
+            TagWidth::Varint => self.read_varint(32)? as u32,
+        };
+        Ok(tag)
+    }
+
+    /// Reads a LEB128 value of at most `bits` bits. Encodings that are longer
+    /// than necessary or carry bits beyond `bits` are rejected, so every value
+    /// has exactly one accepted encoding.
+    pub fn read_varint(&mut self, bits: u32) -> Result<u128, Error> {
+        let mut value: u128 = 0;
+        let mut shift: u32 = 0;
+        loop {
+            let byte = u8::decode_from(self)?;
+            let group = (byte & 0x7f) as u128;
+            if shift >= bits || (bits - shift < 7 && group >> (bits - shift) != 0) {
+                return Err(Error::InvalidVarint);
+            }
+            value |= group << shift;
+            if byte & 0x80 == 0 {
+                // a trailing zero group could have been left out
+                if byte == 0x0u8 && shift != 0 {
+                    return Err(Error::InvalidVarint);
+                }
+                return Ok(value);
+            }
+            shift += 7;
+        }
+    }
+
+    /// Runs `f` for a child value, prefixing `segment` to the path of any
+    /// error it returns. An element of a collection (`PathSegment::Index`) is
+    /// one nesting level deeper; a field stays on the level of its struct,
+    /// since only a wrapper around it can make a type recursive.
+    pub fn nested<T, F>(&mut self, segment: PathSegment, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Decoder<R>) -> Result<T, Error> {
+        let r = match segment {
+            PathSegment::Index(_) => self.enter(f),
+            PathSegment::Field(_) => f(self),
+        };
+        r.map_err(|e| e.located(self.offset, Some(segment)))
+    }
+
+    /// Runs `f` one nesting level deeper without adding to the error path,
+    /// for wrappers like `Option` and `Box` that recursive types go through.
+    pub fn enter<T, F>(&mut self, f: F) -> Result<T, Error>
+        where F: FnOnce(&mut Decoder<R>) -> Result<T, Error> {
+        if let Some(max) = self.config.limits.max_depth {
+            if self.depth >= max {
+                return Err(Error::DepthExceeded(max));
+            }
+        }
+        self.depth += 1;
+        let r = f(self);
+        self.depth -= 1;
+        r
+    }
+
+    /// Reads a graph-mode reference written by `Encoder::write_shared`.
+    pub fn read_shared(&mut self) -> Result<SharedRef, Error> {
+        let word = self.read_varint(64)? as u64;
+        match word {
+            NULL_SHARED => Ok(SharedRef::Null),
+            NEW_SHARED => {
+                self.shared.push(SharedSlot { strong: None, weak: None });
+                Ok(SharedRef::New(FIRST_SHARED_ID + self.shared.len() as u64 - 1))
+            },
+            id if id - FIRST_SHARED_ID < self.shared.len() as u64 => Ok(SharedRef::Back(id)),
+            id => Err(Error::InvalidReference(id)),
+        }
+    }
+
+    // Records the decoded pointer for object `id`; `P` is e.g. `Rc<T>`. Like
+    // the other accessors below, it takes an `id` checked by `read_shared`.
+    fn set_shared<P: Any>(&mut self, id: u64, pointer: P) {
+        self.shared[(id - FIRST_SHARED_ID) as usize].strong = Some(Box::new(pointer));
+    }
+
+    // Records a weak pointer to object `id` while its value is still being
+    // decoded, for `Weak` back-references from inside it.
+    fn set_shared_weak<P: Any>(&mut self, id: u64, weak: P) {
+        self.shared[(id - FIRST_SHARED_ID) as usize].weak = Some(Box::new(weak));
+    }
+
+    // The pointer recorded by `set_shared` for object `id`.
+    fn shared<P: Any + Clone>(&self, id: u64) -> Result<P, Error> {
+        self.shared[(id - FIRST_SHARED_ID) as usize].strong.as_ref()
+            .and_then(|p| p.downcast_ref::<P>())
+            .cloned()
+            .ok_or(Error::InvalidReference(id))
+    }
+
+    // The weak pointer recorded by `set_shared_weak` for object `id`.
+    fn shared_weak<P: Any + Clone>(&self, id: u64) -> Result<P, Error> {
+        self.shared[(id - FIRST_SHARED_ID) as usize].weak.as_ref()
+            .and_then(|p| p.downcast_ref::<P>())
+            .cloned()
+            .ok_or(Error::InvalidReference(id))
+    }
+
+    /// Returns the underlying reader. Bytes peeked through `BufRead` but not
+    /// consumed are dropped.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+impl<R: Read> Decoder<R> {
+    // Bytes left under `Limits::max_bytes`.
+    fn remaining(&self) -> io::Result<u64> {
+        match self.config.limits.max_bytes {
+            Some(max) if self.offset >= max => Err(io::Error::other(Error::LimitExceeded)),
+            Some(max) => Ok(max - self.offset),
+            None => Ok(u64::MAX),
+        }
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let remaining = self.remaining()?;
+        let buf = if (buf.len() as u64) > remaining { &mut buf[..remaining as usize] } else { buf };
+        let n = if self.pos < self.peeked.len() {
+            let n = std::cmp::min(buf.len(), self.peeked.len() - self.pos);
+            buf[..n].copy_from_slice(&self.peeked[self.pos..self.pos + n]);
+            self.pos += n;
+            n
+        } else {
+            self.reader.read(buf)?
+        };
+        self.offset += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read> BufRead for Decoder<R> {
+    // Peeks a single byte at a time so that nothing past the value being
+    // decoded is taken out of the underlying reader.
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.pos >= self.peeked.len() {
+            self.remaining()?;
+            let mut byte = [0x0u8; 1];
+            let n = self.reader.read(&mut byte)?;
+            self.peeked.clear();
+            self.peeked.extend_from_slice(&byte[..n]);
+            self.pos = 0;
+        }
+        Ok(&self.peeked[self.pos..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        let amt = std::cmp::min(amt, self.peeked.len() - self.pos);
+        self.pos += amt;
+        self.offset += amt as u64;
+    }
+}
+
+/// Serializes `value` into a new byte vector.
+pub fn to_vec<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    Config::default().to_vec(value)
+}
+
+/// Serializes `value` into `writer`.
+pub fn to_writer<W: Write, T: Serialize>(writer: &mut W, value: &T) -> Result<(), Error> {
+    Config::default().to_writer(writer, value)
+}
+
+/// Decodes a value from the start of `bytes`, ignoring anything after it.
+pub fn from_slice<T: Decode>(bytes: &[u8]) -> Result<T, Error> {
+    Config::default().from_slice(bytes)
+}
+
+/// Decodes a value from `bytes`, failing if any bytes are left over.
+pub fn from_slice_exact<T: Decode>(bytes: &[u8]) -> Result<T, Error> {
+    Config::default().from_slice_exact(bytes)
+}
+
+/// Decodes a value from `reader`, reading no further than the value itself.
+pub fn from_reader<T: Decode, R: Read>(reader: R) -> Result<T, Error> {
+    Config::default().from_reader(reader)
+}
+
+impl Serialize for bool {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        if *self {
+            e.write_all(&[0x1u8])?;
+        } else {
+            e.write_all(&[0x0u8])?;
+        }
+        Ok(())
+    }
+}
+
+impl DeSerialize for bool {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let mut buffer = [0x0u8; 1];
+        d.read_exact(&mut buffer)?;
+
+        if buffer[0] == 0x0u8 {
+            *self = false;
+        } else if buffer[0] == 0x1u8 {
+            *self = true;
+        } else {
+            return Err(Error::InvalidBool(buffer[0]));
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for char {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        let mut buffer = [0x0u8; 4];
+        e.write_all(self.encode_utf8(&mut buffer).as_bytes())?;
+        Ok(())
+    }
+}
+
+impl DeSerialize for char {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let mut buffer = [0x0u8; 4];
+        d.read_exact(&mut buffer[..1])?;
+
+        if d.config().string_encoding() == StringEncoding::Latin1 {
+            *self = buffer[0] as char;
+            return Ok(());
+        }
+
+        let width = match buffer[0] {
+            0x00..=0x7f => 1,
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => return Err(Error::InvalidUtf8),
+        };
+        d.read_exact(&mut buffer[1..width])?;
+        // from_utf8 rejects overlong forms, surrogates and values past U+10FFFF
+        match std::str::from_utf8(&buffer[..width]) {
+            Ok(s) => *self = s.chars().next().unwrap(),
+            Err(_) => return Err(Error::InvalidUtf8),
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for i8 {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.write_all(&[*self as u8])?;
+        Ok(())
+    }
+}
+
+impl DeSerialize for i8 {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let mut buffer = [0x0u8; 1];
+        d.read_exact(&mut buffer)?;
+        *self = buffer[0] as i8;
+        Ok(())
+    }
+}
+
+impl Serialize for u8 {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.write_all(&[*self])?;
+        Ok(())
+    }
+
+    fn serialize_slice_to<W: Write>(values: &[u8], e: &mut Encoder<W>) -> Result<(), Error> {
+        e.write_all(values)?;
+        Ok(())
+    }
+}
+
+impl DeSerialize for u8 {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let mut buffer = [0x0u8; 1];
+        d.read_exact(&mut buffer)?;
+        *self = buffer[0];
+        Ok(())
+    }
+
+    fn deserialize_slice_from<R: Read>(values: &mut [u8], d: &mut Decoder<R>) -> Result<(), Error> {
+        d.read_exact(values)?;
+        Ok(())
+    }
+}
+
+fn zigzag_encode(v: i128) -> u128 {
+    ((v << 1) ^ (v >> 127)) as u128
+}
+
+fn zigzag_decode(v: u128) -> i128 {
+    ((v >> 1) as i128) ^ -((v & 1) as i128)
+}
+
+macro_rules! impl_number {
+    (@write $e:ident, $v:expr) => {
+        match $e.config().endian() {
+            Endian::Big => $e.write_all(&$v.to_be_bytes())?,
+            Endian::Little => $e.write_all(&$v.to_le_bytes())?,
+        }
+    };
+    (@read $d:ident, $t:ty) => {{
+        let mut buffer = [0x0u8; std::mem::size_of::<$t>()];
+        $d.read_exact(&mut buffer)?;
+        match $d.config().endian() {
+            Endian::Big => <$t>::from_be_bytes(buffer),
+            Endian::Little => <$t>::from_le_bytes(buffer),
+        }
+    }};
+    (float $($t:ty),*) => {$(
+        impl Serialize for $t {
+            crate::__dyn_method!(serialize);
+
+            fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+                impl_number!(@write e, self);
+                Ok(())
+            }
+        }
+
+        impl DeSerialize for $t {
+            crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+                *self = impl_number!(@read d, $t);
+                Ok(())
+            }
+        }
+    )*};
+    (unsigned $($t:ty),*) => {$(
+        impl Serialize for $t {
+            crate::__dyn_method!(serialize);
+
+            fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+                match e.config().int_encoding() {
+                    IntEncoding::Fixed => impl_number!(@write e, self),
+                    IntEncoding::Varint => e.write_varint(*self as u128)?,
+                }
+                Ok(())
+            }
+        }
+
+        impl DeSerialize for $t {
+            crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+                *self = match d.config().int_encoding() {
+                    IntEncoding::Fixed => impl_number!(@read d, $t),
+                    IntEncoding::Varint => d.read_varint(<$t>::BITS)? as $t,
+                };
+                Ok(())
+            }
+        }
+    )*};
+    (signed $($t:ty),*) => {$(
+        impl Serialize for $t {
+            crate::__dyn_method!(serialize);
+
+            fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+                match e.config().int_encoding() {
+                    IntEncoding::Fixed => impl_number!(@write e, self),
+                    IntEncoding::Varint => e.write_varint(zigzag_encode(*self as i128))?,
+                }
+                Ok(())
+            }
+        }
+
+        impl DeSerialize for $t {
+            crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+                *self = match d.config().int_encoding() {
+                    IntEncoding::Fixed => impl_number!(@read d, $t),
+                    IntEncoding::Varint => zigzag_decode(d.read_varint(<$t>::BITS)?) as $t,
+                };
+                Ok(())
+            }
+        }
+    )*};
+    // written through `$wire` so the format doesn't depend on the host
+    (size $($t:ident as $wire:ty),*) => {$(
+        impl Serialize for $t {
+            crate::__dyn_method!(serialize);
+
+            fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+                let overflow = |_| Error::IntegerOverflow { value: *self as i128, target: stringify!($wire) };
+                <$wire>::try_from(*self).map_err(overflow)?.serialize_to(e)
+            }
+        }
+
+        impl DeSerialize for $t {
+            crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+                *self = narrow(<$wire>::decode_from(d)?, stringify!($t))?;
+                Ok(())
+            }
+        }
+    )*};
+}
+
+// Converts an integer read at its wire width to the narrower native type
+// `target` it stands for, e.g. a `u64` written on a 64-bit host to `usize`.
+fn narrow<T, W>(value: W, target: &'static str) -> Result<T, Error>
+    where T: TryFrom<W>, W: Copy + Into<i128> {
+    T::try_from(value).map_err(|_| Error::IntegerOverflow { value: value.into(), target })
+}
+
+impl_number!(unsigned u16, u32, u64, u128);
+impl_number!(signed i16, i32, i64, i128);
+impl_number!(size usize as u64, isize as i64);
+
+crate::decode_default!(bool, char, i8, u8, u16, u32, u64, u128, i16, i32, i64, i128, f32, f64, usize, isize);
+impl_number!(float f32, f64);
+
+/// Encodes the integers and lengths inside `T` as varints regardless of the
+/// surrounding `Config`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Varint<T>(pub T);
+
+impl<T> Serialize for Varint<T>
+    where T: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        let config = e.config().with_int_encoding(IntEncoding::Varint).with_length_width(LengthWidth::Varint);
+        e.scoped(config, |e| self.0.serialize_to(e))
+    }
+}
+
+impl<T> DeSerialize for Varint<T>
+    where T: DeSerialize {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let config = d.config().with_int_encoding(IntEncoding::Varint).with_length_width(LengthWidth::Varint);
+        d.scoped(config, |d| self.0.deserialize_from(d))
+    }
+}
+
+impl<T> Decode for Varint<T>
+    where T: Decode {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        let config = d.config().with_int_encoding(IntEncoding::Varint).with_length_width(LengthWidth::Varint);
+        d.scoped(config, |d| Ok(Varint(T::decode_from(d)?)))
+    }
+
+    fn placeholder() -> Option<Self> {
+        T::placeholder().map(Varint)
+    }
+}
+
+// `serialize_to` needs `Self: Sized`, so the unsized types write through
+// this from their `serialize`, and the owned types that deref to them call
+// it directly.
+fn serialize_slice<T: Serialize, W: Write>(values: &[T], e: &mut Encoder<W>) -> Result<(), Error> {
+    e.write_length(values.len())?;
+    T::serialize_slice_to(values, e)
+}
+
+impl Serialize for str {
+    fn serialize(&self, w: &mut dyn Write) -> Result<(), Error> {
+        Encoder::inherit(w, |e| serialize_slice(self.as_bytes(), e))
+    }
+}
+
+impl Serialize for String {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        serialize_slice(self.as_bytes(), e)
+    }
+}
+
+impl DeSerialize for String {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let length = d.read_length()?;
+        d.check_alloc::<u8>(length)?;
+
+        if length == 0 {
+            *self = String::from("");
+        } else {
+            // grow with the bytes actually read rather than trusting the prefix
+            let mut buffer: Vec<u8> = Vec::new();
+            d.by_ref().take(length as u64).read_to_end(&mut buffer)?;
+            if buffer.len() != length {
+                return Err(Error::UnexpectedEof);
+            }
+            *self = match d.config().string_encoding() {
+                StringEncoding::Utf8 => String::from_utf8(buffer).map_err(|_| Error::InvalidUtf8)?,
+                StringEncoding::Latin1 => buffer.iter().map(|x| *x as char).collect::<String>(),
+            };
+        }
+        Ok(())
+    }
+}
+
+crate::decode_default!(String);
+
+impl<T> Serialize for [T]
+    where T: Serialize {
+    fn serialize(&self, w: &mut dyn Write) -> Result<(), Error> {
+        Encoder::inherit(w, |e| serialize_slice(self, e))
+    }
+}
+
+// Arrays have a statically known length and are written without a prefix.
+impl<T, const N: usize> Serialize for [T; N]
+    where T: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        T::serialize_slice_to(self, e)
+    }
+}
+
+impl<T, const N: usize> DeSerialize for [T; N]
+    where T: DeSerialize {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        T::deserialize_slice_from(self, d)
+    }
+}
+
+// Built element by element, so that no `Default` is needed for any `N`.
+impl<T, const N: usize> Decode for [T; N]
+    where T: Decode {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        let mut values: Vec<T> = Vec::with_capacity(N);
+        for i in 0..N {
+            values.push(d.nested(PathSegment::Index(i), |d| T::decode_from(d))?);
+        }
+        Ok(<[T; N]>::try_from(values).unwrap_or_else(|_| unreachable!()))
+    }
+}
+
+macro_rules! impl_tuple {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name),+> Serialize for ($($name,)+)
+            where $($name: Serialize),+ {
+            crate::__dyn_method!(serialize);
+
+            fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+                $(
+                    self.$index.serialize_to(e)?;
+                )+
+                Ok(())
+            }
+        }
+
+        impl<$($name),+> DeSerialize for ($($name,)+)
+            where $($name: DeSerialize),+ {
+            crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+                $(
+                    d.nested(PathSegment::Field(stringify!($index)), |d| self.$index.deserialize_from(d))?;
+                )+
+                Ok(())
+            }
+        }
+
+        impl<$($name),+> Decode for ($($name,)+)
+            where $($name: Decode),+ {
+            crate::__dyn_method!(decode);
+
+            fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+                Ok(($(
+                    d.nested(PathSegment::Field(stringify!($index)), |d| $name::decode_from(d))?,
+                )+))
+            }
+
+            fn placeholder() -> Option<Self> {
+                Some(($($name::placeholder()?,)+))
+            }
+        }
+    };
+}
+
+impl_tuple!(A 0);
+impl_tuple!(A 0, B 1);
+impl_tuple!(A 0, B 1, C 2);
+impl_tuple!(A 0, B 1, C 2, D 3);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10);
+impl_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7, I 8, J 9, K 10, L 11);
+
+impl Serialize for () {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, _e: &mut Encoder<W>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl DeSerialize for () {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, _d: &mut Decoder<R>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<T> Serialize for PhantomData<T>
+    where T: ?Sized {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, _e: &mut Encoder<W>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+impl<T> DeSerialize for PhantomData<T>
+    where T: ?Sized {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, _d: &mut Decoder<R>) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+crate::decode_default!(());
+crate::decode_default!(impl[T: ?Sized] PhantomData<T>);
+
+impl<T> Serialize for Option<T>
+    where T: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        match self {
+            Some(v) => {
+                true.serialize_to(e)?;
+                v.serialize_to(e)?;
+            },
+            None => {
+                false.serialize_to(e)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T> DeSerialize for Option<T>
+    where T: Decode {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let mut b: bool = false;
+        b.deserialize_from(d)?;
+
+        if !b {
+            *self = None;
+        } else {
+            *self = Some(d.enter(T::decode_from)?);
+        }
+        Ok(())
+    }
+}
+
+crate::decode_default!(impl[T] Option<T> where [T: Decode]);
+
+impl<T, E> Serialize for Result<T, E>
+    where T: Serialize, E: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        match self {
+            Ok(v) => {
+                0x0u8.serialize_to(e)?;
+                v.serialize_to(e)?;
+            },
+            Err(v) => {
+                0x1u8.serialize_to(e)?;
+                v.serialize_to(e)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T, E> DeSerialize for Result<T, E>
+    where T: Decode, E: Decode {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
+        Ok(())
+    }
+}
+
+impl<T, E> Decode for Result<T, E>
+    where T: Decode, E: Decode {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        match u8::decode_from(d)? {
+            0x0u8 => Ok(Ok(d.enter(T::decode_from)?)),
+            0x1u8 => Ok(Err(d.enter(E::decode_from)?)),
+            tag => Err(Error::UnknownTag { name: "Result", tag: tag as u32 }),
+        }
+    }
+
+    fn placeholder() -> Option<Self> {
+        T::placeholder().map(Ok)
+    }
+}
+
+impl<T> Serialize for Vec<T>
+    where T: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        serialize_slice(self, e)
+    }
+}
+
+impl<T> DeSerialize for Vec<T>
+    where T: Decode {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let length = d.read_length()?;
+        d.check_alloc::<T>(length)?;
+
+        let mut vec: Vec<T> = Vec::new();
+        if length != 0 {
+            for i in 0..length {
+                vec.push(d.nested(PathSegment::Index(i), |d| T::decode_from(d))?);
+            }
+        }
+        *self = vec;
+        Ok(())
+    }
+}
+
+crate::decode_default!(impl[T] Vec<T> where [T: Decode]);
+
+impl<T> Serialize for VecDeque<T>
+    where T: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.write_length(self.len())?;
+
+        for v in self.iter() {
+            v.serialize_to(e)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> DeSerialize for VecDeque<T>
+    where T: Decode {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let length = d.read_length()?;
+        d.check_alloc::<T>(length)?;
+
+        let mut vec_deque: VecDeque<T> = VecDeque::new();
+        if length != 0 {
+            for i in 0..length {
+                vec_deque.push_back(d.nested(PathSegment::Index(i), |d| T::decode_from(d))?);
+            }
+        }
+        *self = vec_deque;
+        Ok(())
+    }
+}
 
-pub trait Serialize {
-    fn serialize(&self, w: &mut dyn Write)-> Result<(), Box<dyn Error>>;
+crate::decode_default!(impl[T] VecDeque<T> where [T: Decode]);
+
+impl<T> Serialize for LinkedList<T>
+    where T: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.write_length(self.len())?;
+
+        for v in self.iter() {
+            v.serialize_to(e)?;
+        }
+        Ok(())
+    }
 }
 
-pub trait DeSerialize {
-    fn deserialize(&mut self, r: &mut dyn BufRead)-> Result<(), Box<dyn Error>>;
+impl<T> DeSerialize for LinkedList<T>
+    where T: Decode {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let length = d.read_length()?;
+        d.check_alloc::<T>(length)?;
+
+        let mut list: LinkedList<T> = LinkedList::new();
+        if length != 0 {
+            for i in 0..length {
+                list.push_back(d.nested(PathSegment::Index(i), |d| T::decode_from(d))?);
+            }
+        }
+        *self = list;
+        Ok(())
+    }
 }
 
-impl Serialize for bool {
-    fn serialize(&self, w: &mut dyn Write)-> Result<(), Box<dyn Error>> {
-        if *self {
-            w.write_all(&[0x1u8])?;
-        } else {
-            w.write_all(&[0x0u8])?;
+crate::decode_default!(impl[T] LinkedList<T> where [T: Decode]);
+
+impl<K,V> Serialize for HashMap<K,V>
+    where K: Serialize + Hash, V: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.write_length(self.len())?;
+
+        for (k,v) in self.iter() {
+            k.serialize_to(e)?;
+            v.serialize_to(e)?;
         }
         Ok(())
     }
 }
 
-impl DeSerialize for bool {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 1];
-        r.read_exact(&mut buffer)?;
+impl<K,V> DeSerialize for HashMap<K,V>
+    where K: Decode + Hash + Eq, V: Decode {
+    crate::__dyn_method!(deserialize);
 
-        if buffer[0] == 0x0u8 {
-            *self = false;
-        } else if buffer[0] == 0x1u8 {
-            *self = true;
-        } else {
-            Err("deserialize bool error: invalid bool value")?
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let length = d.read_length()?;
+        d.check_alloc::<(K, V)>(length)?;
+
+        let mut hash_map: HashMap<K,V> = HashMap::new();
+        if length != 0 {
+            for i in 0..length {
+                let k: K = d.nested(PathSegment::Index(i), |d| K::decode_from(d))?;
+                let v: V = d.nested(PathSegment::Index(i), |d| V::decode_from(d))?;
+                hash_map.insert(k,v);
+            }
         }
+        *self = hash_map;
         Ok(())
     }
 }
 
-impl Serialize for char {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&[self.clone() as u8])?;
+crate::decode_default!(impl[K, V] HashMap<K,V> where [K: Decode + Hash + Eq, V: Decode]);
+
+impl<K,V> Serialize for BTreeMap<K,V>
+    where K: Serialize + Ord, V: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.write_length(self.len())?;
+
+        for (k,v) in self.iter() {
+            k.serialize_to(e)?;
+            v.serialize_to(e)?;
+        }
         Ok(())
     }
 }
 
-impl DeSerialize for char {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 1];
-        r.read_exact(&mut buffer)?;
-        *self = buffer[0] as char;
+impl<K,V> DeSerialize for BTreeMap<K,V>
+    where K: Decode + Ord, V: Decode {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let length = d.read_length()?;
+        d.check_alloc::<(K, V)>(length)?;
+
+        let mut btree_map: BTreeMap<K,V> = BTreeMap::new();
+        if length != 0 {
+            for i in 0..length {
+                let k: K = d.nested(PathSegment::Index(i), |d| K::decode_from(d))?;
+                let v: V = d.nested(PathSegment::Index(i), |d| V::decode_from(d))?;
+                btree_map.insert(k,v);
+            }
+        }
+        *self = btree_map;
         Ok(())
     }
 }
 
-impl Serialize for i8 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&[self.clone() as u8])?;
+crate::decode_default!(impl[K, V] BTreeMap<K,V> where [K: Decode + Ord, V: Decode]);
+
+impl<K> Serialize for HashSet<K>
+    where K: Serialize + Hash {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.write_length(self.len())?;
+
+        for k in self.iter() {
+            k.serialize_to(e)?;
+        }
         Ok(())
     }
 }
 
-impl DeSerialize for i8 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 1];
-        r.read_exact(&mut buffer)?;
-        *self = buffer[0] as i8;
+impl<K> DeSerialize for HashSet<K>
+    where K: Decode + Hash + Eq {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let length = d.read_length()?;
+        d.check_alloc::<K>(length)?;
+
+        let mut hash_set: HashSet<K> = HashSet::new();
+        if length != 0 {
+            for i in 0..length {
+                hash_set.insert(d.nested(PathSegment::Index(i), |d| K::decode_from(d))?);
+            }
+        }
+        *self = hash_set;
         Ok(())
     }
 }
 
-impl Serialize for u8 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&[self.clone()])?;
+crate::decode_default!(impl[K] HashSet<K> where [K: Decode + Hash + Eq]);
+
+impl<K> Serialize for BTreeSet<K>
+    where K: Serialize + Ord {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.write_length(self.len())?;
+
+        for k in self.iter() {
+            k.serialize_to(e)?;
+        }
         Ok(())
     }
 }
 
-impl DeSerialize for u8 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 1];
-        r.read_exact(&mut buffer)?;
-        *self = buffer[0];
+impl<K> DeSerialize for BTreeSet<K>
+    where K: Decode + Ord {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let length = d.read_length()?;
+        d.check_alloc::<K>(length)?;
+
+        let mut btree_set: BTreeSet<K> = BTreeSet::new();
+        if length != 0 {
+            for i in 0..length {
+                btree_set.insert(d.nested(PathSegment::Index(i), |d| K::decode_from(d))?);
+            }
+        }
+        *self = btree_set;
+        Ok(())
+    }
+}
+
+crate::decode_default!(impl[K] BTreeSet<K> where [K: Decode + Ord]);
+
+impl<T> Serialize for BinaryHeap<T>
+    where T: Serialize + Ord {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.write_length(self.len())?;
+
+        for k in self.iter() {
+            k.serialize_to(e)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T> DeSerialize for BinaryHeap<T>
+    where T: Decode + Ord {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let length = d.read_length()?;
+        d.check_alloc::<T>(length)?;
+
+        let mut binary_heap: BinaryHeap<T> = BinaryHeap::new();
+        if length != 0 {
+            for i in 0..length {
+                binary_heap.push(d.nested(PathSegment::Index(i), |d| T::decode_from(d))?);
+            }
+        }
+        *self = binary_heap;
         Ok(())
     }
 }
 
-impl Serialize for i16 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&i16::to_be_bytes(self.clone()))?;
+crate::decode_default!(impl[T] BinaryHeap<T> where [T: Decode + Ord]);
+
+// Pointers and borrows are written exactly as the value they point to.
+
+impl<T> Serialize for &T
+    where T: Serialize + ?Sized {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.serialize_unsized(&**self)
+    }
+}
+
+impl<T> Serialize for &mut T
+    where T: Serialize + ?Sized {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.serialize_unsized(&**self)
+    }
+}
+
+impl<T> Serialize for Box<T>
+    where T: Serialize + ?Sized {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.serialize_unsized(&**self)
+    }
+}
+
+impl<T> DeSerialize for Box<T>
+    where T: DeSerialize {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        d.enter(|d| (**self).deserialize_from(d))
+    }
+}
+
+impl<T> Decode for Box<T>
+    where T: Decode {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        Ok(Box::new(d.enter(T::decode_from)?))
+    }
+
+    fn placeholder() -> Option<Self> {
+        T::placeholder().map(Box::new)
+    }
+}
+
+impl DeSerialize for Box<str> {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl DeSerialize for i16 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 2];
-        r.read_exact(&mut buffer)?;
-        *self = i16::from_be_bytes(buffer);
+impl Decode for Box<str> {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        Ok(String::decode_from(d)?.into_boxed_str())
+    }
+}
+
+impl<T> DeSerialize for Box<[T]>
+    where T: Decode {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl Serialize for u16 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&u16::to_be_bytes(self.clone()))?;
+impl<T> Decode for Box<[T]>
+    where T: Decode {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        Ok(Vec::<T>::decode_from(d)?.into_boxed_slice())
+    }
+}
+
+// Graph-mode decoding of a new object, shared by the pointer and its `Weak`.
+trait DecodeShared: Sized {
+    fn decode_shared<R: Read>(d: &mut Decoder<R>, id: u64) -> Result<Self, Error>;
+}
+
+// A shared value can not be filled in place, so `Rc` and `Arc` always decode
+// into a new allocation.
+
+macro_rules! impl_shared {
+    ($($ptr:ident $module:ident),*) => {$(
+        impl<T> Serialize for $ptr<T>
+            where T: Serialize + ?Sized {
+            crate::__dyn_method!(serialize);
+
+            fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+                if e.config().graph() && !e.write_shared($ptr::as_ptr(self) as *const u8 as usize)? {
+                    return Ok(());
+                }
+                e.serialize_unsized(&**self)
+            }
+        }
+
+        impl<T> DecodeShared for $ptr<T>
+            where T: Decode + 'static {
+            fn decode_shared<R: Read>(d: &mut Decoder<R>, id: u64) -> Result<Self, Error> {
+                let pointer = match T::placeholder() {
+                    // built around a `Weak` so that the value can point back at itself
+                    Some(placeholder) => {
+                        let mut r = Ok(());
+                        let pointer = $ptr::new_cyclic(|weak| {
+                            d.set_shared_weak(id, weak.clone());
+                            d.enter(T::decode_from).unwrap_or_else(|e| {
+                                r = Err(e);
+                                placeholder
+                            })
+                        });
+                        r?;
+                        pointer
+                    },
+                    None => $ptr::new(d.enter(T::decode_from)?),
+                };
+                d.set_shared(id, pointer.clone());
+                Ok(pointer)
+            }
+        }
+
+        impl<T> DeSerialize for $ptr<T>
+            where T: Decode + 'static {
+            crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+                *self = Self::decode_from(d)?;
+                Ok(())
+            }
+        }
+
+        impl<T> Decode for $ptr<T>
+            where T: Decode + 'static {
+            crate::__dyn_method!(decode);
+
+            fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+                if !d.config().graph() {
+                    return Ok($ptr::new(d.enter(T::decode_from)?));
+                }
+                match d.read_shared()? {
+                    SharedRef::New(id) => $ptr::decode_shared(d, id),
+                    // fails for an object still being decoded, i.e. a strong cycle
+                    SharedRef::Back(id) => d.shared(id),
+                    SharedRef::Null => Err(Error::InvalidReference(NULL_SHARED)),
+                }
+            }
+
+            fn placeholder() -> Option<Self> {
+                T::placeholder().map($ptr::new)
+            }
+        }
+
+        impl DeSerialize for $ptr<str> {
+            crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+                *self = Self::decode_from(d)?;
+                Ok(())
+            }
+        }
+
+        impl Decode for $ptr<str> {
+            crate::__dyn_method!(decode);
+
+            fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+                if !d.config().graph() {
+                    return Ok($ptr::from(String::decode_from(d)?));
+                }
+                match d.read_shared()? {
+                    SharedRef::New(id) => {
+                        let pointer: $ptr<str> = $ptr::from(String::decode_from(d)?);
+                        d.set_shared(id, pointer.clone());
+                        Ok(pointer)
+                    },
+                    SharedRef::Back(id) => d.shared(id),
+                    SharedRef::Null => Err(Error::InvalidReference(NULL_SHARED)),
+                }
+            }
+        }
+
+        impl<T> DeSerialize for $ptr<[T]>
+            where T: Decode + 'static {
+            crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+                *self = Self::decode_from(d)?;
+                Ok(())
+            }
+        }
+
+        impl<T> Decode for $ptr<[T]>
+            where T: Decode + 'static {
+            crate::__dyn_method!(decode);
+
+            fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+                if !d.config().graph() {
+                    return Ok($ptr::from(Vec::<T>::decode_from(d)?));
+                }
+                match d.read_shared()? {
+                    SharedRef::New(id) => {
+                        let pointer: $ptr<[T]> = $ptr::from(Vec::<T>::decode_from(d)?);
+                        d.set_shared(id, pointer.clone());
+                        Ok(pointer)
+                    },
+                    SharedRef::Back(id) => d.shared(id),
+                    SharedRef::Null => Err(Error::InvalidReference(NULL_SHARED)),
+                }
+            }
+        }
+
+        // A `Weak` is written like the pointer it upgrades to, or as a null
+        // reference once the object is gone. Outside graph mode the link
+        // could not be restored, so it is refused.
+        impl<T> Serialize for std::$module::Weak<T>
+            where T: Serialize + ?Sized {
+            crate::__dyn_method!(serialize);
+
+            fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+                if !e.config().graph() {
+                    return Err(Error::Custom(String::from("`Weak` can only be serialized in graph mode")));
+                }
+                match self.upgrade() {
+                    Some(pointer) => pointer.serialize_to(e),
+                    None => e.write_varint(NULL_SHARED as u128),
+                }
+            }
+        }
+
+        impl<T> DeSerialize for std::$module::Weak<T>
+            where T: Decode + 'static {
+            crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+                if !d.config().graph() {
+                    return Err(Error::Custom(String::from("`Weak` can only be deserialized in graph mode")));
+                }
+                *self = match d.read_shared()? {
+                    SharedRef::New(id) => $ptr::downgrade(&$ptr::<T>::decode_shared(d, id)?),
+                    SharedRef::Back(id) => match d.shared::<$ptr<T>>(id) {
+                        Ok(pointer) => $ptr::downgrade(&pointer),
+                        Err(_) => d.shared_weak(id)?,
+                    },
+                    SharedRef::Null => std::$module::Weak::new(),
+                };
+                Ok(())
+            }
+        }
+
+        crate::decode_default!(impl[T] std::$module::Weak<T> where [T: Decode + 'static]);
+    )*};
+}
+
+impl_shared!(Rc rc, Arc sync);
+
+impl<T> Serialize for RefCell<T>
+    where T: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        let value = self.try_borrow().map_err(|_| Error::Custom(String::from("`RefCell` is mutably borrowed")))?;
+        value.serialize_to(e)
+    }
+}
+
+impl<T> DeSerialize for RefCell<T>
+    where T: DeSerialize {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        self.get_mut().deserialize_from(d)
+    }
+}
+
+impl<T> Decode for RefCell<T>
+    where T: Decode {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        Ok(RefCell::new(T::decode_from(d)?))
+    }
+
+    fn placeholder() -> Option<Self> {
+        T::placeholder().map(RefCell::new)
+    }
+}
+
+impl<T> Serialize for Mutex<T>
+    where T: Serialize {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        let value = self.lock().map_err(|_| Error::Custom(String::from("`Mutex` is poisoned")))?;
+        value.serialize_to(e)
+    }
+}
+
+impl<T> DeSerialize for Mutex<T>
+    where T: DeSerialize {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let value = self.get_mut().map_err(|_| Error::Custom(String::from("`Mutex` is poisoned")))?;
+        value.deserialize_from(d)
+    }
+}
+
+impl<T> Decode for Mutex<T>
+    where T: Decode {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        Ok(Mutex::new(T::decode_from(d)?))
+    }
+
+    fn placeholder() -> Option<Self> {
+        T::placeholder().map(Mutex::new)
+    }
+}
+
+impl<B> Serialize for Cow<'_, B>
+    where B: Serialize + ToOwned + ?Sized {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        e.serialize_unsized(&**self)
+    }
+}
+
+impl<B> DeSerialize for Cow<'_, B>
+    where B: ToOwned + ?Sized, B::Owned: Decode {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl DeSerialize for u16 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 2];
-        r.read_exact(&mut buffer)?;
-        *self = u16::from_be_bytes(buffer);
-        Ok(())
+impl<B> Decode for Cow<'_, B>
+    where B: ToOwned + ?Sized, B::Owned: Decode {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        Ok(Cow::Owned(B::Owned::decode_from(d)?))
     }
 }
 
-impl Serialize for i32 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&i32::to_be_bytes(self.clone()))?;
-        Ok(())
+impl Serialize for Duration {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        self.as_secs().serialize_to(e)?;
+        self.subsec_nanos().serialize_to(e)
     }
 }
 
-impl DeSerialize for i32 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 4];
-        r.read_exact(&mut buffer)?;
-        *self = i32::from_be_bytes(buffer);
+impl DeSerialize for Duration {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let secs = u64::decode_from(d)?;
+        let nanos = u32::decode_from(d)?;
+        if nanos >= 1_000_000_000 {
+            return Err(Error::InvalidNanos(nanos));
+        }
+        *self = Duration::new(secs, nanos);
         Ok(())
     }
 }
 
-impl Serialize for u32 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&u32::to_be_bytes(self.clone()))?;
-        Ok(())
+crate::decode_default!(Duration);
+
+// A `SystemTime` is written as whole seconds since UNIX_EPOCH, rounded down
+// so that they are negative before 1970, plus the nanoseconds past them.
+impl Serialize for SystemTime {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        let (secs, nanos) = match self.duration_since(UNIX_EPOCH) {
+            Ok(after) => (after.as_secs() as i128, after.subsec_nanos()),
+            Err(before) => {
+                let before = before.duration();
+                match before.subsec_nanos() {
+                    0 => (-(before.as_secs() as i128), 0),
+                    nanos => (-(before.as_secs() as i128) - 1, 1_000_000_000 - nanos),
+                }
+            },
+        };
+        let overflow = |_| Error::IntegerOverflow { value: secs, target: "i64" };
+        i64::try_from(secs).map_err(overflow)?.serialize_to(e)?;
+        nanos.serialize_to(e)
     }
 }
 
-impl DeSerialize for u32 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 4];
-        r.read_exact(&mut buffer)?;
-        *self = u32::from_be_bytes(buffer);
+impl DeSerialize for SystemTime {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl Serialize for i64 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&i64::to_be_bytes(self.clone()))?;
-        Ok(())
+impl Decode for SystemTime {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        let secs = i64::decode_from(d)?;
+        let nanos = u32::decode_from(d)?;
+        if nanos >= 1_000_000_000 {
+            return Err(Error::InvalidNanos(nanos));
+        }
+        let time = if secs >= 0 {
+            UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+        } else {
+            UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+                .and_then(|t| t.checked_add(Duration::from_nanos(nanos as u64)))
+        };
+        // the range of `SystemTime` depends on the platform
+        time.ok_or(Error::IntegerOverflow { value: secs as i128, target: "SystemTime" })
     }
 }
 
-impl DeSerialize for i64 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 8];
-        r.read_exact(&mut buffer)?;
-        *self = i64::from_be_bytes(buffer);
-        Ok(())
+// The address types are written as their raw octets, `IpAddr` and
+// `SocketAddr` behind a one-byte family tag: 0 for V4, 1 for V6.
+impl Serialize for Ipv4Addr {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        self.octets().serialize_to(e)
     }
 }
 
-impl Serialize for u64 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&u64::to_be_bytes(self.clone()))?;
+impl DeSerialize for Ipv4Addr {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl DeSerialize for u64 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 8];
-        r.read_exact(&mut buffer)?;
-        *self = u64::from_be_bytes(buffer);
-        Ok(())
+impl Decode for Ipv4Addr {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        Ok(Ipv4Addr::from(<[u8; 4]>::decode_from(d)?))
     }
 }
 
-impl Serialize for i128 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&i128::to_be_bytes(self.clone()))?;
-        Ok(())
+impl Serialize for Ipv6Addr {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        self.octets().serialize_to(e)
     }
 }
 
-impl DeSerialize for i128 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 16];
-        r.read_exact(&mut buffer)?;
-        *self = i128::from_be_bytes(buffer);
+impl DeSerialize for Ipv6Addr {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl Serialize for u128 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&u128::to_be_bytes(self.clone()))?;
-        Ok(())
+impl Decode for Ipv6Addr {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        Ok(Ipv6Addr::from(<[u8; 16]>::decode_from(d)?))
     }
 }
 
-impl DeSerialize for u128 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 16];
-        r.read_exact(&mut buffer)?;
-        *self = u128::from_be_bytes(buffer);
-        Ok(())
+impl Serialize for IpAddr {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        match self {
+            IpAddr::V4(ip) => {
+                0x0u8.serialize_to(e)?;
+                ip.serialize_to(e)
+            },
+            IpAddr::V6(ip) => {
+                0x1u8.serialize_to(e)?;
+                ip.serialize_to(e)
+            },
+        }
     }
 }
 
-impl Serialize for f32 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&f32::to_be_bytes(self.clone()))?;
+impl DeSerialize for IpAddr {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl DeSerialize for f32 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 4];
-        r.read_exact(&mut buffer)?;
-        *self = f32::from_be_bytes(buffer);
-        Ok(())
+impl Decode for IpAddr {
+    crate::__dyn_method!(decode);
+
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        match u8::decode_from(d)? {
+            0x0u8 => Ok(IpAddr::V4(Ipv4Addr::decode_from(d)?)),
+            0x1u8 => Ok(IpAddr::V6(Ipv6Addr::decode_from(d)?)),
+            tag => Err(Error::UnknownTag { name: "IpAddr", tag: tag as u32 }),
+        }
     }
 }
 
-impl Serialize for f64 {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        w.write_all(&f64::to_be_bytes(self.clone()))?;
-        Ok(())
+impl Serialize for SocketAddrV4 {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        self.ip().serialize_to(e)?;
+        self.port().serialize_to(e)
     }
 }
 
-impl DeSerialize for f64 {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut buffer = [0x0u8; 8];
-        r.read_exact(&mut buffer)?;
-        *self = f64::from_be_bytes(buffer);
+impl DeSerialize for SocketAddrV4 {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl Serialize for str {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+impl Decode for SocketAddrV4 {
+    crate::__dyn_method!(decode);
 
-        for c in self.chars() {
-            c.serialize(w)?;
-        }
-        Ok(())
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        let ip = Ipv4Addr::decode_from(d)?;
+        Ok(SocketAddrV4::new(ip, u16::decode_from(d)?))
     }
 }
 
-impl Serialize for String {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+impl Serialize for SocketAddrV6 {
+    crate::__dyn_method!(serialize);
 
-        for c in self.chars() {
-            c.serialize(w)?;
-        }
-        Ok(())
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        self.ip().serialize_to(e)?;
+        self.port().serialize_to(e)?;
+        self.flowinfo().serialize_to(e)?;
+        self.scope_id().serialize_to(e)
     }
 }
 
-impl DeSerialize for String {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut length :u32 = 0u32;
-        length.deserialize(r)?;
+impl DeSerialize for SocketAddrV6 {
+    crate::__dyn_method!(deserialize);
 
-        if length == 0 {
-            *self = String::from("");
-        } else {
-            // vec new with capacity
-            let mut buffer: Vec<u8> = Vec::with_capacity(length as usize);
-            // set size
-            buffer.resize(length as usize, 0x0 as u8);
-            r.read_exact(&mut buffer)?;
-            *self = buffer.iter().map(|x| *x as char).collect::<String>();
-        }
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl<T> Serialize for [T]
-    where T: Serialize {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+impl Decode for SocketAddrV6 {
+    crate::__dyn_method!(decode);
 
-        for v in self.iter() {
-            v.serialize(w)?;
-        }
-        Ok(())
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        let ip = Ipv6Addr::decode_from(d)?;
+        let port = u16::decode_from(d)?;
+        let flowinfo = u32::decode_from(d)?;
+        Ok(SocketAddrV6::new(ip, port, flowinfo, u32::decode_from(d)?))
     }
 }
 
-impl<T> Serialize for Option<T>
-    where T: Serialize {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
+impl Serialize for SocketAddr {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
         match self {
-            Some(v) => {
-                true.serialize(w)?;
-                v.serialize(w)?;
+            SocketAddr::V4(addr) => {
+                0x0u8.serialize_to(e)?;
+                addr.serialize_to(e)
+            },
+            SocketAddr::V6(addr) => {
+                0x1u8.serialize_to(e)?;
+                addr.serialize_to(e)
             },
-            None => {
-                false.serialize(w)?;
-            }
         }
-        Ok(())
     }
 }
 
-impl<T> DeSerialize for Option<T>
-    where T: DeSerialize {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut b: bool = false;
-        b.deserialize(r)?;
+impl DeSerialize for SocketAddr {
+    crate::__dyn_method!(deserialize);
 
-        if !b {
-            *self = None;
-        } else {
-            match self {
-                Some(t) => {
-                    t.deserialize(r)?;
-                },
-                _ => {
-                    return Err("can not deserialize to None type")?;
-                }
-            }
-        }
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl<T> Serialize for Vec<T>
-    where T: Serialize {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+impl Decode for SocketAddr {
+    crate::__dyn_method!(decode);
 
-        for v in self.iter() {
-            v.serialize(w)?;
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        match u8::decode_from(d)? {
+            0x0u8 => Ok(SocketAddr::V4(SocketAddrV4::decode_from(d)?)),
+            0x1u8 => Ok(SocketAddr::V6(SocketAddrV6::decode_from(d)?)),
+            tag => Err(Error::UnknownTag { name: "SocketAddr", tag: tag as u32 }),
         }
-        Ok(())
     }
 }
 
-impl<T> DeSerialize for Vec<T>
-    where T: DeSerialize + Default {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut length :u32 = 0u32;
-        length.deserialize(r)?;
+// OS strings and paths are written like a `Vec<u8>` of their raw bytes. Only
+// Unix exposes those; elsewhere they have to be valid UTF-8.
+#[cfg(unix)]
+fn os_str_bytes(s: &OsStr) -> Result<&[u8], Error> {
+    Ok(s.as_bytes())
+}
 
-        let mut vec: Vec<T> = Vec::new();
-        if length != 0 {
-            for _ in 0..length {
-                let mut v: T = T::default();
-                v.deserialize(r)?;
-                vec.push(v);
-            }
-        }
-        *self = vec;
-        Ok(())
-    }
+#[cfg(not(unix))]
+fn os_str_bytes(s: &OsStr) -> Result<&[u8], Error> {
+    s.to_str().map(str::as_bytes).ok_or(Error::InvalidUtf8)
 }
 
-impl<T> Serialize for VecDeque<T>
-    where T: Serialize {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+#[cfg(unix)]
+fn os_string_from(bytes: Vec<u8>) -> Result<OsString, Error> {
+    Ok(OsString::from_vec(bytes))
+}
 
-        for v in self.iter() {
-            v.serialize(w)?;
-        }
-        Ok(())
+#[cfg(not(unix))]
+fn os_string_from(bytes: Vec<u8>) -> Result<OsString, Error> {
+    String::from_utf8(bytes).map(OsString::from).map_err(|_| Error::InvalidUtf8)
+}
+
+impl Serialize for OsStr {
+    fn serialize(&self, w: &mut dyn Write) -> Result<(), Error> {
+        Encoder::inherit(w, |e| serialize_slice(os_str_bytes(self)?, e))
     }
 }
 
-impl<T> DeSerialize for VecDeque<T>
-    where T: DeSerialize + Default {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut length :u32 = 0u32;
-        length.deserialize(r)?;
+impl Serialize for OsString {
+    crate::__dyn_method!(serialize);
 
-        let mut vec_deque: VecDeque<T> = VecDeque::new();
-        if length != 0 {
-            for _ in 0..length {
-                let mut v: T = T::default();
-                v.deserialize(r)?;
-                vec_deque.push_back(v);
-            }
-        }
-        *self = vec_deque;
-        Ok(())
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        serialize_slice(os_str_bytes(self)?, e)
     }
 }
 
-impl<T> Serialize for LinkedList<T>
-    where T: Serialize {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+impl DeSerialize for OsString {
+    crate::__dyn_method!(deserialize);
 
-        for v in self.iter() {
-            v.serialize(w)?;
-        }
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = os_string_from(Vec::<u8>::decode_from(d)?)?;
         Ok(())
     }
 }
 
-impl<T> DeSerialize for LinkedList<T>
-    where T: DeSerialize + Default {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut length :u32 = 0u32;
-        length.deserialize(r)?;
+crate::decode_default!(OsString);
 
-        let mut list: LinkedList<T> = LinkedList::new();
-        if length != 0 {
-            for _ in 0..length {
-                let mut v: T = T::default();
-                v.deserialize(r)?;
-                list.push_back(v);
-            }
-        }
-        *self = list;
-        Ok(())
+impl Serialize for Path {
+    fn serialize(&self, w: &mut dyn Write) -> Result<(), Error> {
+        Encoder::inherit(w, |e| serialize_slice(os_str_bytes(self.as_os_str())?, e))
     }
 }
 
-impl<K,V> Serialize for HashMap<K,V>
-    where K: Serialize + Hash, V: Serialize {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+impl Serialize for PathBuf {
+    crate::__dyn_method!(serialize);
 
-        for (k,v) in self.iter() {
-            k.serialize(w)?;
-            v.serialize(w)?;
-        }
-        Ok(())
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        serialize_slice(os_str_bytes(self.as_os_str())?, e)
     }
 }
 
-impl<K,V> DeSerialize for HashMap<K,V>
-    where K: DeSerialize + Default + Hash + Eq, V: DeSerialize + Default {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut length :u32 = 0u32;
-        length.deserialize(r)?;
+impl DeSerialize for PathBuf {
+    crate::__dyn_method!(deserialize);
 
-        let mut hash_map: HashMap<K,V> = HashMap::new();
-        if length != 0 {
-            for _ in 0..length {
-                let mut k: K = K::default();
-                let mut v: V = V::default();
-                k.deserialize(r)?;
-                v.deserialize(r)?;
-                hash_map.insert(k,v);
-            }
-        }
-        *self = hash_map;
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = PathBuf::from(OsString::decode_from(d)?);
         Ok(())
     }
 }
 
-impl<K,V> Serialize for BTreeMap<K,V>
-    where K: Serialize + Ord, V: Serialize + Default {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+crate::decode_default!(PathBuf);
 
-        for (k,v) in self.iter() {
-            k.serialize(w)?;
-            v.serialize(w)?;
-        }
+impl DeSerialize for Box<Path> {
+    crate::__dyn_method!(deserialize);
+
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        *self = Self::decode_from(d)?;
         Ok(())
     }
 }
 
-impl<K,V> DeSerialize for BTreeMap<K,V>
-    where K: DeSerialize + Default + Ord, V: DeSerialize + Default{
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut length :u32 = 0u32;
-        length.deserialize(r)?;
+impl Decode for Box<Path> {
+    crate::__dyn_method!(decode);
 
-        let mut btree_map: BTreeMap<K,V> = BTreeMap::new();
-        if length != 0 {
-            for _ in 0..length {
-                let mut k: K = K::default();
-                let mut v: V = V::default();
-                k.deserialize(r)?;
-                v.deserialize(r)?;
-                btree_map.insert(k,v);
-            }
-        }
-        *self = btree_map;
-        Ok(())
+    fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+        Ok(PathBuf::decode_from(d)?.into_boxed_path())
     }
 }
 
-impl<K> Serialize for HashSet<K>
-    where K: Serialize + Hash {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+// C strings are written without their terminating nul.
+impl Serialize for CStr {
+    fn serialize(&self, w: &mut dyn Write) -> Result<(), Error> {
+        Encoder::inherit(w, |e| serialize_slice(self.to_bytes(), e))
+    }
+}
 
-        for k in self.iter() {
-            k.serialize(w)?;
-        }
-        Ok(())
+impl Serialize for CString {
+    crate::__dyn_method!(serialize);
+
+    fn serialize_to<W: Write>(&self, e: &mut Encoder<W>) -> Result<(), Error> {
+        serialize_slice(self.to_bytes(), e)
     }
 }
 
-impl<K> DeSerialize for HashSet<K>
-    where K: DeSerialize + Default + Hash + Eq {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut length :u32 = 0u32;
-        length.deserialize(r)?;
+impl DeSerialize for CString {
+    crate::__dyn_method!(deserialize);
 
-        let mut hash_set: HashSet<K> = HashSet::new();
-        if length != 0 {
-            for _ in 0..length {
-                let mut k: K = K::default();
-                k.deserialize(r)?;
-                hash_set.insert(k);
-            }
-        }
-        *self = hash_set;
+    fn deserialize_from<R: Read>(&mut self, d: &mut Decoder<R>) -> Result<(), Error> {
+        let bytes = Vec::<u8>::decode_from(d)?;
+        *self = CString::new(bytes).map_err(|e| Error::InteriorNul(e.nul_position()))?;
         Ok(())
     }
 }
 
-impl<K> Serialize for BTreeSet<K>
-    where K: Serialize + Ord {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+crate::decode_default!(CString);
 
-        for k in self.iter() {
-            k.serialize(w)?;
+// The dyn entry point of a trait, for impls that write the generic method
+// and only need `serialize`, `deserialize` or `decode` to reach it.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __dyn_method {
+    (serialize) => {
+        fn serialize(&self, w: &mut dyn ::std::io::Write) -> ::std::result::Result<(), $crate::Error> {
+            $crate::Encoder::inherit(w, |e| e.serialize(self))
         }
-        Ok(())
-    }
+    };
+    (deserialize) => {
+        fn deserialize(&mut self, r: &mut dyn ::std::io::BufRead) -> ::std::result::Result<(), $crate::Error> {
+            $crate::Decoder::inherit(r, |d| d.deserialize(self))
+        }
+    };
+    (decode) => {
+        fn decode(r: &mut dyn ::std::io::BufRead) -> ::std::result::Result<Self, $crate::Error> {
+            $crate::Decoder::inherit(r, |d| d.decode())
+        }
+    };
 }
 
-impl<K> DeSerialize for BTreeSet<K>
-    where K: DeSerialize + Default + Ord {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut length :u32 = 0u32;
-        length.deserialize(r)?;
+/// Implements `Serialize` for a struct by writing the listed fields in order.
+///
+/// ```ignore
+/// serialize_struct!(Xxxx, a, b, c);
+/// serialize_struct!(Pair, 0, 1);
+/// serialize_struct!(impl[T] Envelope<T> where [T: Serialize], id, body);
+/// serialize_struct!(transparent UserId);
+/// ```
+///
+/// The `transparent` form is for newtypes and writes the single field
+/// exactly as the inner value.
+#[macro_export]
+macro_rules! serialize_struct {
+    (transparent impl[$($generics:tt)*] $struct_name:ty $(where [$($bounds:tt)*])?) => {
+        impl<$($generics)*> $crate::Serialize for $struct_name $(where $($bounds)*)? {
+            $crate::__dyn_method!(serialize);
 
-        let mut btree_set: BTreeSet<K> = BTreeSet::new();
-        if length != 0 {
-            for _ in 0..length {
-                let mut k: K = K::default();
-                k.deserialize(r)?;
-                btree_set.insert(k);
+            fn serialize_to<__W: ::std::io::Write>(&self, e: &mut $crate::Encoder<__W>) -> ::std::result::Result<(), $crate::Error> {
+                $crate::Serialize::serialize_to(&self.0, e)
             }
         }
-        *self = btree_set;
-        Ok(())
-    }
+    };
+    (transparent $struct_name:ty) => {
+        $crate::serialize_struct!(transparent impl[] $struct_name);
+    };
+    (impl[$($generics:tt)*] $struct_name:ty $(where [$($bounds:tt)*])?, $($member_name:tt),*) => {
+        impl<$($generics)*> $crate::Serialize for $struct_name $(where $($bounds)*)? {
+            $crate::__dyn_method!(serialize);
+
+            fn serialize_to<__W: ::std::io::Write>(&self, e: &mut $crate::Encoder<__W>) -> ::std::result::Result<(), $crate::Error> {
+                $(
+                    $crate::Serialize::serialize_to(&self.$member_name, e)?;
+                )*
+                ::std::result::Result::Ok(())
+            }
+        }
+    };
+    ($struct_name:ty, $($member_name:tt),*) => {
+        $crate::serialize_struct!(impl[] $struct_name, $($member_name),*);
+    };
 }
 
-impl<T> Serialize for BinaryHeap<T>
-    where T: Serialize + Ord {
-    fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-        let length = self.len() as u32;
-        length.serialize(w)?;
+/// Implements `DeSerialize` for a struct, taking the same arguments as
+/// `serialize_struct!`. Fields are filled in place.
+///
+/// When the struct has a `Default` value it also gets a `Decode` impl that
+/// fills in that value, so it can be read inside `Vec`, `Option` and the
+/// other containers. Structs without one can use
+/// `serializable_struct!(decode ...)` to be built field by field instead.
+#[macro_export]
+macro_rules! deserialize_struct {
+    (@fill transparent impl[$($generics:tt)*] $struct_name:ty $(where [$($bounds:tt)*])?) => {
+        impl<$($generics)*> $crate::DeSerialize for $struct_name $(where $($bounds)*)? {
+            $crate::__dyn_method!(deserialize);
 
-        for k in self.iter() {
-            k.serialize(w)?;
+            fn deserialize_from<__R: ::std::io::Read>(&mut self, d: &mut $crate::Decoder<__R>) -> ::std::result::Result<(), $crate::Error> {
+                $crate::DeSerialize::deserialize_from(&mut self.0, d)
+            }
         }
-        Ok(())
-    }
+    };
+    (@fill impl[$($generics:tt)*] $struct_name:ty $(where [$($bounds:tt)*])?, $($member_name:tt),*) => {
+        impl<$($generics)*> $crate::DeSerialize for $struct_name $(where $($bounds)*)? {
+            $crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<__R: ::std::io::Read>(&mut self, d: &mut $crate::Decoder<__R>) -> ::std::result::Result<(), $crate::Error> {
+                $(
+                    d.nested($crate::PathSegment::Field(::std::stringify!($member_name)),
+                        |d| $crate::DeSerialize::deserialize_from(&mut self.$member_name, d))?;
+                )*
+                ::std::result::Result::Ok(())
+            }
+        }
+    };
+    // The higher-ranked bound keeps the impl from failing to build for a
+    // struct without `Default`; it simply does not apply to it.
+    (@default impl[$($generics:tt)*] $struct_name:ty $(where [$($bounds:tt)*])?) => {
+        $crate::decode_default!(impl[$($generics)*] $struct_name
+            where [for<'__a> $struct_name: ::std::default::Default, $($($bounds)*)?]);
+    };
+    (transparent impl[$($generics:tt)*] $struct_name:ty $(where [$($bounds:tt)*])?) => {
+        $crate::deserialize_struct!(@fill transparent impl[$($generics)*] $struct_name $(where [$($bounds)*])?);
+        $crate::deserialize_struct!(@default impl[$($generics)*] $struct_name $(where [$($bounds)*])?);
+    };
+    (transparent $struct_name:ty) => {
+        $crate::deserialize_struct!(transparent impl[] $struct_name);
+    };
+    (impl[$($generics:tt)*] $struct_name:ty $(where [$($bounds:tt)*])?, $($member_name:tt),*) => {
+        $crate::deserialize_struct!(@fill impl[$($generics)*] $struct_name $(where [$($bounds)*])?, $($member_name),*);
+        $crate::deserialize_struct!(@default impl[$($generics)*] $struct_name $(where [$($bounds)*])?);
+    };
+    ($struct_name:ty, $($member_name:tt),*) => {
+        $crate::deserialize_struct!(impl[] $struct_name, $($member_name),*);
+    };
 }
 
-impl<T> DeSerialize for BinaryHeap<T>
-    where T: DeSerialize + Default + Ord {
-    fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
-        let mut length :u32 = 0u32;
-        length.deserialize(r)?;
+/// Implements both `Serialize` and `DeSerialize` from one field list, in the
+/// forms `serialize_struct!` accepts.
+///
+/// Prefixed with `decode`, it implements `Decode` by building the struct
+/// field by field instead of from its `Default` value, for types that have
+/// none.
+///
+/// ```ignore
+/// serializable_struct!(Xxxx, a, b, c);
+/// serializable_struct!(decode Order, id, items);
+/// ```
+#[macro_export]
+macro_rules! serializable_struct {
+    (decode impl[$($generics:tt)*] $struct_name:ty $(where [$($bounds:tt)*])?, $($member_name:tt),*) => {
+        $crate::serialize_struct!(impl[$($generics)*] $struct_name $(where [$($bounds)*])?, $($member_name),*);
+        $crate::deserialize_struct!(@fill impl[$($generics)*] $struct_name $(where [$($bounds)*])?, $($member_name),*);
+
+        impl<$($generics)*> $crate::Decode for $struct_name $(where $($bounds)*)? {
+            $crate::__dyn_method!(decode);
+
+            fn decode_from<__R: ::std::io::Read>(d: &mut $crate::Decoder<__R>) -> ::std::result::Result<Self, $crate::Error> {
+                ::std::result::Result::Ok(Self {
+                    $(
+                        $member_name: d.nested($crate::PathSegment::Field(::std::stringify!($member_name)),
+                            |d| $crate::Decode::decode_from(d))?,
+                    )*
+                })
+            }
+        }
+    };
+    (decode $struct_name:ty, $($member_name:tt),*) => {
+        $crate::serializable_struct!(decode impl[] $struct_name, $($member_name),*);
+    };
+    (transparent $($rest:tt)*) => {
+        $crate::serialize_struct!(transparent $($rest)*);
+        $crate::deserialize_struct!(transparent $($rest)*);
+    };
+    ($($rest:tt)*) => {
+        $crate::serialize_struct!($($rest)*);
+        $crate::deserialize_struct!($($rest)*);
+    };
+}
 
-        let mut binary_heap: BinaryHeap<T> = BinaryHeap::new();
-        if length != 0 {
-            for _ in 0..length {
-                let mut t: T = T::default();
-                t.deserialize(r)?;
-                binary_heap.push(t);
+/// Implements `Decode` for a `DeSerialize + Default` type by filling in its
+/// default value, in the forms `serialize_struct!` accepts for the type.
+/// `deserialize_struct!` already does this; it is for hand-written
+/// `DeSerialize` impls.
+///
+/// ```ignore
+/// decode_default!(Xxxx, Pair);
+/// decode_default!(impl[T: Default] Envelope<T> where [T: DeSerialize]);
+/// ```
+#[macro_export]
+macro_rules! decode_default {
+    (impl[$($generics:tt)*] $type_name:ty $(where [$($bounds:tt)*])?) => {
+        impl<$($generics)*> $crate::Decode for $type_name $(where $($bounds)*)? {
+            $crate::__dyn_method!(decode);
+
+            fn decode_from<__R: ::std::io::Read>(d: &mut $crate::Decoder<__R>) -> ::std::result::Result<Self, $crate::Error> {
+                let mut value: Self = ::std::default::Default::default();
+                $crate::DeSerialize::deserialize_from(&mut value, d)?;
+                ::std::result::Result::Ok(value)
+            }
+
+            fn placeholder() -> ::std::option::Option<Self> {
+                ::std::option::Option::Some(::std::default::Default::default())
             }
         }
-        *self = binary_heap;
-        Ok(())
-    }
+    };
+    ($($type_name:ty),+ $(,)?) => {
+        $( $crate::decode_default!(impl[] $type_name); )+
+    };
 }
 
+/// Implements `Serialize` for an enum from a list of its variants and their
+/// tags. The tag is written as a `u32` unless a width (`u8`, `u16`, `u32` or
+/// `varint`) is given, followed by the fields in the order listed.
+///
+/// ```ignore
+/// serialize_enum!(Shape, u8, {
+///     Empty = 0,
+///     Circle(r) = 1,
+///     Rect { w, h } = 2,
+/// });
+/// ```
+///
+/// A tag that doesn't fit into the width, or that two variants share, fails
+/// to compile:
+///
+/// ```compile_fail
+/// # use serialize_rs::serialize_enum;
+/// enum Shape { Empty, Circle(u32) }
+/// serialize_enum!(Shape, u8, { Empty = 0, Circle(r) = 0 });
+/// ```
+///
+/// ```compile_fail
+/// # use serialize_rs::serialize_enum;
+/// enum Shape { Empty, Circle(u32) }
+/// serialize_enum!(Shape, u8, { Empty = 0, Circle(r) = 256 });
+/// ```
 #[macro_export]
-macro_rules! serialize_struct {
-    ($struct_name:ty, $($member_name:ident),*) => {
-        impl Serialize for $struct_name {
-            fn serialize(&self, w: &mut dyn Write) -> Result<(), Box<dyn Error>> {
-                $(
-                    self.$member_name.serialize(w)?;
-                )*
-                Ok(())
+macro_rules! serialize_enum {
+    (@width u8) => { $crate::TagWidth::U8 };
+    (@width u16) => { $crate::TagWidth::U16 };
+    (@width u32) => { $crate::TagWidth::U32 };
+    (@width varint) => { $crate::TagWidth::Varint };
+    (@max u8) => { 0xff_u64 };
+    (@max u16) => { 0xffff_u64 };
+    (@max u32) => { 0xffff_ffff_u64 };
+    (@max varint) => { 0xffff_ffff_u64 };
+    (@check $width:ident, $($tag:expr),*) => {
+        const _: () = {
+            let tags: &[u64] = &[$(($tag) as u64),*];
+            let mut i = 0;
+            while i < tags.len() {
+                ::std::assert!(tags[i] <= $crate::serialize_enum!(@max $width), "enum tag does not fit into the tag width");
+                let mut j = 0;
+                while j < i {
+                    ::std::assert!(tags[i] != tags[j], "duplicate enum tag");
+                    j += 1;
+                }
+                i += 1;
+            }
+        };
+    };
+    ($enum_name:ident, { $($variants:tt)* }) => {
+        $crate::serialize_enum!($enum_name, u32, { $($variants)* });
+    };
+    ($enum_name:ident, $width:ident, {
+        $($variant:ident $(( $($field:ident),* ))? $({ $($named:ident),* })? = $tag:expr),* $(,)?
+    }) => {
+        $crate::serialize_enum!(@check $width, $($tag),*);
+
+        impl $crate::Serialize for $enum_name {
+            $crate::__dyn_method!(serialize);
+
+            fn serialize_to<__W: ::std::io::Write>(&self, e: &mut $crate::Encoder<__W>) -> ::std::result::Result<(), $crate::Error> {
+                match self {
+                    $(
+                        $enum_name::$variant $(( $($field),* ))? $({ $($named),* })? => {
+                            e.write_tag($crate::serialize_enum!(@width $width), $tag)?;
+                            $($( $crate::Serialize::serialize_to($field, e)?; )*)?
+                            $($( $crate::Serialize::serialize_to($named, e)?; )*)?
+                        },
+                    )*
+                }
+                ::std::result::Result::Ok(())
             }
         }
     };
 }
 
+/// Implements `DeSerialize` and `Decode` for an enum, taking the same
+/// arguments as `serialize_enum!` and checking the tags the same way. The fields of the decoded variant are built
+/// with `Decode`; a tag matching no variant fails with `Error::UnknownTag`.
 #[macro_export]
-macro_rules! deserialize_struct {
-    ($struct_name:ty, $($member_name:ident),*) => {
-        impl DeSerialize for $struct_name {
-            fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
+macro_rules! deserialize_enum {
+    ($enum_name:ident, { $($variants:tt)* }) => {
+        $crate::deserialize_enum!($enum_name, u32, { $($variants)* });
+    };
+    ($enum_name:ident, $width:ident, {
+        $($variant:ident $(( $($field:ident),* ))? $({ $($named:ident),* })? = $tag:expr),* $(,)?
+    }) => {
+        $crate::serialize_enum!(@check $width, $($tag),*);
+
+        impl $crate::DeSerialize for $enum_name {
+            $crate::__dyn_method!(deserialize);
+
+            fn deserialize_from<__R: ::std::io::Read>(&mut self, d: &mut $crate::Decoder<__R>) -> ::std::result::Result<(), $crate::Error> {
+                *self = <Self as $crate::Decode>::decode_from(d)?;
+                ::std::result::Result::Ok(())
+            }
+        }
+
+        impl $crate::Decode for $enum_name {
+            $crate::__dyn_method!(decode);
+
+            fn decode_from<__R: ::std::io::Read>(d: &mut $crate::Decoder<__R>) -> ::std::result::Result<Self, $crate::Error> {
+                let tag = d.read_tag($crate::serialize_enum!(@width $width))?;
                 $(
-                    self.$member_name.deserialize(r)?;
+                    if tag == $tag {
+                        $($(
+                            let $field = d.nested($crate::PathSegment::Field(::std::stringify!($field)),
+                                |d| $crate::Decode::decode_from(d))?;
+                        )*)?
+                        $($(
+                            let $named = d.nested($crate::PathSegment::Field(::std::stringify!($named)),
+                                |d| $crate::Decode::decode_from(d))?;
+                        )*)?
+                        return ::std::result::Result::Ok($enum_name::$variant $(( $($field),* ))? $({ $($named),* })?);
+                    }
                 )*
-                Ok(())
+                ::std::result::Result::Err($crate::Error::UnknownTag { name: ::std::stringify!($enum_name), tag })
             }
         }
     };
 }
 
 #[cfg(test)]
+#[allow(clippy::unnecessary_cast, clippy::get_first, clippy::single_match, clippy::char_lit_as_u8,
+    clippy::bool_assert_comparison, clippy::assertions_on_constants)]
 mod tests {
-    use crate::{Serialize, DeSerialize};
-    use std::io::{BufWriter, Cursor, Write, BufRead};
+    use crate::{Serialize, DeSerialize, Decode, Encoder, Decoder, Error, PathSegment, Config, Endian, IntEncoding, LengthWidth, Limits, Varint,
+        StringEncoding};
+    use std::io::{BufWriter, Cursor, Write, BufRead, Read};
     use std::collections::{VecDeque, LinkedList, HashMap, BTreeMap, HashSet, BTreeSet, BinaryHeap};
-    use std::error::Error;
 
     #[test]
     fn test_serialize_bool() {
@@ -646,16 +2964,86 @@ mod tests {
         assert_eq!(*(buf.buffer().get(0).unwrap()), 0x1u8);
     }
 
-    #[test]
-    fn test_deserialize_bool() {
-        let mut buf = Cursor::new(vec![0x01u8]);
-        let mut val: bool = false;
-        let r = val.deserialize(&mut buf);
-        match r {
-            Err(e) => assert!(false, "{}", e.to_string()),
-            _ => {}
+    #[test]
+    fn test_deserialize_bool() {
+        let mut buf = Cursor::new(vec![0x01u8]);
+        let mut val: bool = false;
+        let r = val.deserialize(&mut buf);
+        match r {
+            Err(e) => assert!(false, "{}", e.to_string()),
+            _ => {}
+        }
+        assert_eq!(val, true);
+    }
+
+    #[test]
+    fn test_deserialize_errors() {
+        let mut buf = Cursor::new(vec![0x02u8]);
+        let mut val: bool = false;
+        let e = val.deserialize(&mut buf).unwrap_err();
+        assert!(matches!(e.root(), Error::InvalidBool(0x02u8)));
+        assert_eq!(e.offset(), Some(1));
+
+        let mut buf = Cursor::new(vec![0x01u8, 0x02u8]);
+        let mut val: u32 = 0u32;
+        let e = val.deserialize(&mut buf).unwrap_err();
+        assert!(matches!(e.root(), Error::UnexpectedEof));
+        assert_eq!(e.offset(), Some(2));
+    }
+
+    #[test]
+    fn test_deserialize_error_location() {
+        #[derive(Debug, Default)]
+        struct Item {
+            a: u32,
+            c: bool
+        }
+
+        #[derive(Debug, Default)]
+        struct Order {
+            id: u32,
+            items: HashMap<String, Item>
+        }
+
+        #[derive(Debug, Default)]
+        struct Book {
+            orders: Vec<Order>
         }
-        assert_eq!(val, true);
+
+        serialize_struct!(Item, a, c);
+        deserialize_struct!(Item, a, c);
+        serialize_struct!(Order, id, items);
+        deserialize_struct!(Order, id, items);
+        serialize_struct!(Book, orders);
+        deserialize_struct!(Book, orders);
+
+        let mut items = HashMap::new();
+        items.insert(String::from("sku"), Item { a: 1, c: true });
+        let book = Book { orders: vec![Order { id: 1, items: HashMap::new() }, Order { id: 2, items }] };
+        let mut buf = BufWriter::new(Vec::new());
+        let _ = book.serialize(&mut buf);
+
+        // corrupt the trailing bool
+        let mut bytes = buf.buffer().to_vec();
+        let last = bytes.len() - 1;
+        bytes[last] = 0x02u8;
+
+        let mut buf = Cursor::new(bytes.as_slice());
+        let mut val: Book = Book::default();
+        let e = val.deserialize(&mut buf).unwrap_err();
+        assert!(matches!(e.root(), Error::InvalidBool(0x02u8)));
+        assert_eq!(e.offset(), Some(bytes.len() as u64));
+        assert_eq!(e.path(), &[PathSegment::Field("orders"), PathSegment::Index(1), PathSegment::Field("items"),
+            PathSegment::Index(0), PathSegment::Field("c")]);
+        assert_eq!(e.to_string(), format!("invalid bool value: 0x02 at byte {} in `orders[1].items[0].c`", bytes.len()));
+
+        // truncated stream
+        let mut buf = Cursor::new(&bytes[..14]);
+        let mut val: Book = Book::default();
+        let e = val.deserialize(&mut buf).unwrap_err();
+        assert!(matches!(e.root(), Error::UnexpectedEof));
+        assert_eq!(e.offset(), Some(14));
+        assert_eq!(e.path(), &[PathSegment::Field("orders"), PathSegment::Index(1), PathSegment::Field("id")]);
     }
 
     #[test]
@@ -675,6 +3063,21 @@ mod tests {
         assert_eq!(val, 'a');
     }
 
+    #[test]
+    fn test_serialize_deserialize_unicode_char() {
+        for (c, len) in [('a', 1), ('\u{e9}', 2), ('\u{20ac}', 3), ('\u{1f600}', 4)] {
+            let bytes = crate::to_vec(&c).unwrap();
+            assert_eq!(bytes.len(), len);
+            assert_eq!(crate::from_slice::<char>(&bytes).unwrap(), c);
+        }
+
+        // surrogate, overlong encoding, value past U+10FFFF, stray continuation byte
+        for bytes in [&[0xedu8, 0xa0u8, 0x80u8][..], &[0xc0u8, 0x80u8], &[0xf4u8, 0x90u8, 0x80u8, 0x80u8], &[0x80u8]] {
+            let e = crate::from_slice::<char>(bytes).unwrap_err();
+            assert!(matches!(e.root(), Error::InvalidUtf8));
+        }
+    }
+
     #[test]
     fn test_serialize_i8() {
         let mut buf = BufWriter::new(Vec::new());
@@ -974,13 +3377,40 @@ mod tests {
         assert_eq!(val, String::from("abcd"));
     }
 
+    #[test]
+    fn test_serialize_deserialize_unicode_string() {
+        let v = String::from("h\u{e9}llo \u{4e16}\u{754c}");
+        let bytes = crate::to_vec(&v).unwrap();
+        assert_eq!(bytes.len(), 4 + v.len());
+        assert_eq!(&bytes[..4], &(v.len() as u32).to_be_bytes());
+        assert_eq!(&bytes[4..], v.as_bytes());
+        assert_eq!(crate::from_slice::<String>(&bytes).unwrap(), v);
+
+        let e = crate::from_slice::<String>(&[0x0u8, 0x0u8, 0x0u8, 0x2u8, 0xc3u8, 0x28u8]).unwrap_err();
+        assert!(matches!(e.root(), Error::InvalidUtf8));
+    }
+
+    #[test]
+    fn test_deserialize_latin1_string() {
+        // "c\u{e9}" as written by the old one-byte-per-char encoding
+        let bytes = vec![0x0u8, 0x0u8, 0x0u8, 0x2u8, 0x63u8, 0xe9u8, 0xe9u8];
+        let mut d = Decoder::with_config(bytes.as_slice(), Config::new().with_string_encoding(StringEncoding::Latin1));
+        let val: String = d.decode().unwrap();
+        assert_eq!(val, String::from("c\u{e9}"));
+        let val: char = d.decode().unwrap();
+        assert_eq!(val, '\u{e9}');
+
+        assert!(crate::from_slice::<String>(&bytes).is_err());
+    }
+
     // with generic type
     #[test]
     fn test_serialize_slice() {
         let l = ['a', 'b', 'c', 'd'];
         let mut buf = BufWriter::new(Vec::new());
         assert_eq!(buf.buffer().len(), 0);
-        let _ = l.serialize(&mut buf);
+        // arrays themselves are written without a length prefix
+        let _ = l[..].serialize(&mut buf);
         assert_eq!(buf.buffer().len(), 8);
         assert_eq!(*(buf.buffer().get(4).unwrap()) as char, 'a');
         assert_eq!(*(buf.buffer().get(5).unwrap()) as char, 'b');
@@ -1021,20 +3451,45 @@ mod tests {
         let _ = val.deserialize(&mut buf);
         assert_eq!(val, None);
 
-        // error: try to deserialize to None type
+        // deserialize Some into a None slot
         let o: Option<String> = Some(String::from("abcd"));
         let mut buf = BufWriter::new(Vec::new());
         let _ = o.serialize(&mut buf);
 
         let mut buf = Cursor::new(buf.buffer());
         let mut val: Option<String> = None;
-        let r = val.deserialize(&mut buf);
-        match r {
-            Err(e) => {
-                assert_eq!(e.to_string(), String::from("can not deserialize to None type"));
-            },
-            _ => {}
+        val.deserialize(&mut buf).unwrap();
+        assert_eq!(val, Some(String::from("abcd")));
+
+        // an inner type with no Default value
+        #[derive(Debug, PartialEq)]
+        struct Id(u32);
+
+        impl Decode for Id {
+            crate::__dyn_method!(decode);
+
+            fn decode_from<R: Read>(d: &mut Decoder<R>) -> Result<Self, Error> {
+                Ok(Id(u32::decode_from(d)?))
+            }
+        }
+
+        let mut val: Option<Id> = None;
+        val.deserialize(&mut crate::to_vec(&Some(7u32)).unwrap().as_slice()).unwrap();
+        assert_eq!(val, Some(Id(7)));
+
+        // an inner struct from the macros, as accepted before `Decode`
+        #[derive(Debug, Default, PartialEq)]
+        struct Inner {
+            a: u16
         }
+
+        serializable_struct!(Inner, a);
+
+        let mut val: Option<Inner> = None;
+        val.deserialize(&mut crate::to_vec(&Some(Inner { a: 3 })).unwrap().as_slice()).unwrap();
+        assert_eq!(val, Some(Inner { a: 3 }));
+        let val: Option<Vec<Inner>> = crate::from_slice(&[1, 0, 0, 0, 1, 0, 9]).unwrap();
+        assert_eq!(val, Some(vec![Inner { a: 9 }]));
     }
 
     #[test]
@@ -1152,6 +3607,19 @@ mod tests {
         assert_eq!(*val.get(&'b').unwrap(), 1i8);
         assert_eq!(*val.get(&'c').unwrap(), 2i8);
         assert_eq!(*val.get(&'d').unwrap(), 3i8);
+
+        // keys need no `Debug`; a bad value is located by its entry index
+        #[derive(Default, PartialEq, Eq, PartialOrd, Ord)]
+        struct Sku(u16);
+
+        serializable_struct!(Sku, 0);
+
+        let by_sku: BTreeMap<Sku, bool> = vec![(Sku(1), true), (Sku(2), false)].into_iter().collect();
+        let mut bytes = crate::to_vec(&by_sku).unwrap();
+        assert!(crate::from_slice_exact::<BTreeMap<Sku, bool>>(&bytes).unwrap() == by_sku);
+        bytes[9] = 0x02u8;
+        let e = crate::from_slice::<BTreeMap<Sku, bool>>(&bytes).err().unwrap();
+        assert_eq!(e.path(), &[PathSegment::Index(1)]);
     }
 
     #[test]
@@ -1220,6 +3688,1044 @@ mod tests {
         assert_eq!(val.pop().unwrap(), 'a');
     }
 
+    #[test]
+    fn test_decode_without_default() {
+        #[derive(Debug, PartialEq, Eq, Hash)]
+        struct Id(u32);
+
+        impl Serialize for Id {
+            fn serialize(&self, w: &mut dyn Write) -> Result<(), Error> {
+                self.0.serialize(w)
+            }
+        }
+
+        impl Decode for Id {
+            fn decode(r: &mut dyn BufRead) -> Result<Self, Error> {
+                let id = u32::decode(r)?;
+                if id == 0 {
+                    Err("id must not be zero")?
+                }
+                Ok(Id(id))
+            }
+        }
+
+        let mut buf = BufWriter::new(Vec::new());
+        let _ = vec![Id(1), Id(2)].serialize(&mut buf);
+        assert_eq!(buf.buffer().len(), 12);
+
+        let mut buf = Cursor::new(buf.buffer());
+        let mut val: Vec<Id> = Vec::new();
+        let _ = val.deserialize(&mut buf);
+        assert_eq!(val, vec![Id(1), Id(2)]);
+
+        let mut hash_map: HashMap<Id, String> = HashMap::new();
+        hash_map.insert(Id(7), String::from("seven"));
+        let mut buf = BufWriter::new(Vec::new());
+        let _ = hash_map.serialize(&mut buf);
+
+        let mut buf = Cursor::new(buf.buffer());
+        let val = HashMap::<Id, String>::decode(&mut buf).unwrap();
+        assert_eq!(val.get(&Id(7)).unwrap(), "seven");
+
+        // a zero id is rejected by Id::decode while filling the vector
+        let mut buf = Cursor::new(vec![0x0u8, 0x0u8, 0x0u8, 0x1u8, 0x0u8, 0x0u8, 0x0u8, 0x0u8]);
+        let mut val: Vec<Id> = Vec::new();
+        let r = val.deserialize(&mut buf);
+        assert_eq!(r.unwrap_err().to_string(), String::from("id must not be zero at byte 8 in `[0]`"));
+    }
+
+    #[test]
+    fn test_generic_encode_decode() {
+        let mut hash_map: BTreeMap<String, Vec<u16>> = BTreeMap::new();
+        hash_map.insert(String::from("a"), vec![1u16, 2u16]);
+        hash_map.insert(String::from("b"), vec![]);
+
+        let mut buf = BufWriter::new(Vec::new());
+        let _ = hash_map.serialize(&mut buf);
+
+        let mut e = Encoder::new(Vec::new());
+        let _ = e.serialize(&hash_map);
+        assert_eq!(e.into_inner(), buf.buffer().to_vec());
+
+        // a plain Read is enough for the generic path
+        let r: Box<dyn Read> = Box::new(Cursor::new(buf.buffer().to_vec()));
+        let mut d = Decoder::new(r);
+        let val: BTreeMap<String, Vec<u16>> = d.decode().unwrap();
+        assert_eq!(val, hash_map);
+        assert_eq!(d.offset(), buf.buffer().len() as u64);
+    }
+
+    #[test]
+    fn test_dyn_serialize() {
+        let values: Vec<Box<dyn Serialize>> = vec![Box::new(7u16), Box::new(String::from("ab")), Box::new(Some(true))];
+        let bytes = crate::to_vec(&values).unwrap();
+        assert_eq!(bytes, vec![0x00u8, 0x00u8, 0x00u8, 0x03u8, 0x00u8, 0x07u8, 0x00u8, 0x00u8, 0x00u8, 0x02u8, 0x61u8, 0x62u8,
+            0x01u8, 0x01u8]);
+
+        // the config still reaches the value behind a `&dyn Serialize`
+        let value: &dyn Serialize = &(1u16, "ab");
+        let config = Config::new().with_endian(Endian::Little).with_length_width(LengthWidth::U8);
+        assert_eq!(config.to_vec(&value).unwrap(), vec![0x01u8, 0x00u8, 0x02u8, 0x61u8, 0x62u8]);
+    }
+
+    #[test]
+    fn test_decoder_buf_read() {
+        // hand-written impl relying on BufRead, reached through the generic path
+        #[derive(Debug, Default, PartialEq)]
+        struct Line(String);
+
+        impl DeSerialize for Line {
+            fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Error> {
+                self.0.clear();
+                r.read_line(&mut self.0)?;
+                Ok(())
+            }
+        }
+
+        decode_default!(Line);
+
+        let r: Box<dyn Read> = Box::new(Cursor::new(b"ab\ncd\n".to_vec()));
+        let mut d = Decoder::new(r);
+        let val: Vec<Line> = vec![d.decode().unwrap(), d.decode().unwrap()];
+        assert_eq!(val, vec![Line(String::from("ab\n")), Line(String::from("cd\n"))]);
+        assert_eq!(d.offset(), 6);
+    }
+
+    #[test]
+    fn test_to_vec_from_slice() {
+        let v: Vec<String> = vec![String::from("ab"), String::from("c")];
+        let bytes = crate::to_vec(&v).unwrap();
+        assert_eq!(bytes.len(), 15);
+
+        let mut out: Vec<u8> = Vec::new();
+        let _ = crate::to_writer(&mut out, &v);
+        assert_eq!(out, bytes);
+
+        let val: Vec<String> = crate::from_slice(&bytes).unwrap();
+        assert_eq!(val, v);
+        let val: Vec<String> = crate::from_slice_exact(&bytes).unwrap();
+        assert_eq!(val, v);
+
+        // trailing bytes are only an error for from_slice_exact
+        let mut longer = bytes.clone();
+        longer.push(0x0u8);
+        let val: Vec<String> = crate::from_slice(&longer).unwrap();
+        assert_eq!(val, v);
+        let e = crate::from_slice_exact::<Vec<String>>(&longer).unwrap_err();
+        assert!(matches!(e.root(), Error::TrailingBytes(1)));
+        assert_eq!(e.offset(), Some(15));
+
+        // from_reader leaves the rest of the stream in place
+        let mut r = Cursor::new(longer);
+        let val: Vec<String> = crate::from_reader(&mut r).unwrap();
+        assert_eq!(val, v);
+        assert_eq!(r.position(), 15);
+
+        let e = crate::from_slice::<Vec<String>>(&bytes[..6]).unwrap_err();
+        assert!(matches!(e.root(), Error::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_config_endian_and_length_width() {
+        let config = Config::new().with_endian(Endian::Little).with_length_width(LengthWidth::U16);
+        let v: Vec<u32> = vec![0x01020304u32, 0x05060708u32];
+        let bytes = config.to_vec(&v).unwrap();
+        assert_eq!(bytes, vec![0x02u8, 0x00u8, 0x04u8, 0x03u8, 0x02u8, 0x01u8, 0x08u8, 0x07u8, 0x06u8, 0x05u8]);
+        assert_eq!(config.from_slice_exact::<Vec<u32>>(&bytes).unwrap(), v);
+
+        let bytes = Config::default().to_vec(&0.5f64).unwrap();
+        assert_eq!(bytes, crate::to_vec(&0.5f64).unwrap());
+        assert_eq!(bytes, 0.5f64.to_be_bytes().to_vec());
+        let bytes = config.to_vec(&0.5f64).unwrap();
+        assert_eq!(bytes, 0.5f64.to_le_bytes().to_vec());
+        assert_eq!(config.from_slice::<f64>(&bytes).unwrap(), 0.5f64);
+
+        // the config reaches fields of macro-generated impls
+        #[derive(Debug, Default, PartialEq)]
+        struct Xxxx {
+            a: i16,
+            b: String
+        }
+
+        serialize_struct!(Xxxx, a, b);
+        deserialize_struct!(Xxxx, a, b);
+
+        let x = Xxxx { a: -2, b: String::from("ab") };
+        let bytes = config.to_vec(&x).unwrap();
+        assert_eq!(bytes, vec![0xfeu8, 0xffu8, 0x02u8, 0x00u8, 0x61u8, 0x62u8]);
+        assert_eq!(config.from_slice::<Xxxx>(&bytes).unwrap(), x);
+    }
+
+    #[test]
+    fn test_varint() {
+        let config = Config::new().with_int_encoding(IntEncoding::Varint).with_length_width(LengthWidth::Varint);
+        assert_eq!(config.to_vec(&0u64).unwrap(), vec![0x00u8]);
+        assert_eq!(config.to_vec(&127u32).unwrap(), vec![0x7fu8]);
+        assert_eq!(config.to_vec(&300u16).unwrap(), vec![0xacu8, 0x02u8]);
+        assert_eq!(config.to_vec(&-1i64).unwrap(), vec![0x01u8]);
+        assert_eq!(config.to_vec(&1i32).unwrap(), vec![0x02u8]);
+        assert_eq!(config.to_vec(&-65i16).unwrap(), vec![0x81u8, 0x01u8]);
+        assert_eq!(config.to_vec(&String::from("ab")).unwrap(), vec![0x02u8, 0x61u8, 0x62u8]);
+        assert_eq!(config.to_vec(&u64::MAX).unwrap().len(), 10);
+
+        assert_eq!(config.from_slice_exact::<u64>(&config.to_vec(&u64::MAX).unwrap()).unwrap(), u64::MAX);
+        assert_eq!(config.from_slice_exact::<u128>(&config.to_vec(&u128::MAX).unwrap()).unwrap(), u128::MAX);
+        assert_eq!(config.from_slice_exact::<i128>(&config.to_vec(&i128::MIN).unwrap()).unwrap(), i128::MIN);
+        assert_eq!(config.from_slice_exact::<i16>(&config.to_vec(&i16::MAX).unwrap()).unwrap(), i16::MAX);
+        assert_eq!(config.from_slice_exact::<u16>(&[0xffu8, 0xffu8, 0x03u8]).unwrap(), u16::MAX);
+
+        let v: Vec<i64> = vec![-3, 0, 5000, i64::MIN];
+        assert_eq!(config.from_slice_exact::<Vec<i64>>(&config.to_vec(&v).unwrap()).unwrap(), v);
+
+        // overlong, non-minimal and out of range encodings
+        for bytes in [&[0x80u8, 0x00u8][..], &[0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x80u8, 0x01u8][..]] {
+            let e = config.from_slice::<u32>(bytes).err().unwrap();
+            assert!(matches!(e.root(), Error::InvalidVarint));
+        }
+        let e = config.from_slice::<u16>(&[0xffu8, 0xffu8, 0x04u8]).err().unwrap();
+        assert!(matches!(e.root(), Error::InvalidVarint));
+        let e = config.from_slice::<u8>(&[]).err().unwrap();
+        assert!(matches!(e.root(), Error::UnexpectedEof));
+
+        // per value, inside the default config
+        #[derive(Debug, Default, PartialEq)]
+        struct Xxxx {
+            a: Varint<u64>,
+            b: u64,
+            c: Varint<Vec<u32>>
+        }
+
+        serialize_struct!(Xxxx, a, b, c);
+        deserialize_struct!(Xxxx, a, b, c);
+
+        let x = Xxxx { a: Varint(1), b: 1, c: Varint(vec![1, 300]) };
+        let bytes = crate::to_vec(&x).unwrap();
+        assert_eq!(bytes, vec![0x01u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x01u8, 0x02u8, 0x01u8, 0xacu8, 0x02u8]);
+        assert_eq!(crate::from_slice_exact::<Xxxx>(&bytes).unwrap(), x);
+    }
+
+    #[test]
+    fn test_decode_limits() {
+        // a 4 GiB string announced by a 4 byte prefix
+        let bytes = vec![0xffu8, 0xffu8, 0xffu8, 0xffu8, 0x61u8];
+        let e = crate::from_slice::<String>(&bytes).err().unwrap();
+        assert!(matches!(e.root(), Error::UnexpectedEof));
+
+        let config = Config::new().with_limits(Limits::new().with_max_alloc(1024));
+        let e = config.from_slice::<String>(&bytes).err().unwrap();
+        assert!(matches!(e.root(), Error::LimitExceeded));
+        assert_eq!(e.offset(), Some(4));
+        let bytes = crate::to_vec(&vec![0u64; 200]).unwrap();
+        let e = config.from_slice::<Vec<u64>>(&bytes).err().unwrap();
+        assert!(matches!(e.root(), Error::LimitExceeded));
+        assert_eq!(config.from_slice::<Vec<u64>>(&bytes[..132]).err().unwrap().offset(), Some(4));
+        assert_eq!(config.from_slice::<Vec<u8>>(&crate::to_vec(&vec![0u8; 200]).unwrap()).unwrap().len(), 200);
+        // zero-sized elements read no bytes, so only max_alloc stops the loop
+        let e = config.from_slice::<Vec<()>>(&[0xffu8, 0xffu8, 0xffu8, 0xffu8]).err().unwrap();
+        assert!(matches!(e.root(), Error::LimitExceeded));
+        let e = config.from_slice::<Vec<std::marker::PhantomData<u64>>>(&[0xffu8, 0xffu8, 0xffu8, 0xffu8]).err().unwrap();
+        assert!(matches!(e.root(), Error::LimitExceeded));
+        assert_eq!(config.from_slice::<Vec<()>>(&crate::to_vec(&vec![(); 3]).unwrap()).unwrap().len(), 3);
+
+        let config = Config::new().with_limits(Limits::new().with_max_length(2));
+        let mut m: HashMap<u8, u8> = HashMap::new();
+        m.insert(1, 1);
+        m.insert(2, 2);
+        assert_eq!(config.from_slice::<HashMap<u8, u8>>(&crate::to_vec(&m).unwrap()).unwrap(), m);
+        m.insert(3, 3);
+        let e = config.from_slice::<HashMap<u8, u8>>(&crate::to_vec(&m).unwrap()).err().unwrap();
+        assert!(matches!(e.root(), Error::LimitExceeded));
+
+        let config = Config::new().with_limits(Limits::new().with_max_bytes(8));
+        let bytes = crate::to_vec(&vec![String::from("ab"), String::from("cd")]).unwrap();
+        let e = config.from_slice::<Vec<String>>(&bytes).err().unwrap();
+        assert!(matches!(e.root(), Error::LimitExceeded));
+        assert_eq!(e.to_string(), "decoding limit exceeded at byte 8 in `[0]`");
+        let config = Config::new().with_limits(Limits::new().with_max_bytes(bytes.len() as u64));
+        assert_eq!(config.from_slice_exact::<Vec<String>>(&bytes).unwrap().len(), 2);
+
+        let config = Config::new().with_limits(Limits::new().with_max_depth(2));
+        let v: Vec<Vec<u8>> = vec![vec![1]];
+        assert_eq!(config.from_slice::<Vec<Vec<u8>>>(&crate::to_vec(&v).unwrap()).unwrap(), v);
+        let v: Vec<Vec<Vec<u8>>> = vec![vec![vec![1]]];
+        let e = config.from_slice::<Vec<Vec<Vec<u8>>>>(&crate::to_vec(&v).unwrap()).err().unwrap();
+        assert!(matches!(e.root(), Error::DepthExceeded(2)));
+        assert_eq!(e.path(), &[PathSegment::Index(0), PathSegment::Index(0), PathSegment::Index(0)]);
+
+        // a field stays on the level of its struct; the elements of a `Vec`
+        // are one level down
+        #[derive(Debug, Default, PartialEq)]
+        struct Xxxx {
+            a: Vec<u8>
+        }
+
+        serialize_struct!(Xxxx, a);
+        deserialize_struct!(Xxxx, a);
+
+        let x = Xxxx { a: vec![1, 2] };
+        let bytes = crate::to_vec(&x).unwrap();
+        let config = Config::new().with_limits(Limits::new().with_max_depth(1));
+        assert_eq!(config.from_slice::<Xxxx>(&bytes).unwrap(), x);
+        let config = Config::new().with_limits(Limits::new().with_max_depth(0));
+        let e = config.from_slice::<Xxxx>(&bytes).err().unwrap();
+        assert!(matches!(e.root(), Error::DepthExceeded(0)));
+    }
+
+    #[test]
+    fn test_serialize_deserialize_result_unit_phantom() {
+        use std::marker::PhantomData;
+
+        let ok: Result<u16, String> = Ok(7);
+        let err: Result<u16, String> = Err(String::from("no"));
+        assert_eq!(crate::to_vec(&ok).unwrap(), vec![0x00u8, 0x00u8, 0x07u8]);
+        assert_eq!(crate::to_vec(&err).unwrap(), vec![0x01u8, 0x00u8, 0x00u8, 0x00u8, 0x02u8, 0x6eu8, 0x6fu8]);
+
+        let mut r: Result<u16, String> = Ok(0);
+        r.deserialize(&mut crate::to_vec(&err).unwrap().as_slice()).unwrap();
+        assert_eq!(r, err);
+        r.deserialize(&mut crate::to_vec(&ok).unwrap().as_slice()).unwrap();
+        assert_eq!(r, ok);
+
+        let e = Decoder::new(&[0x02u8][..]).deserialize(&mut r).err().unwrap();
+        assert!(matches!(e.root(), Error::UnknownTag { name: "Result", tag: 2 }));
+        let results = vec![ok.clone(), err.clone()];
+        assert_eq!(crate::from_slice_exact::<Vec<Result<u16, String>>>(&crate::to_vec(&results).unwrap()).unwrap(), results);
+        let e = crate::from_slice::<Result<u16, String>>(&[0x01u8, 0x00u8]).err().unwrap();
+        assert!(matches!(e.root(), Error::UnexpectedEof));
+
+        assert_eq!(crate::to_vec(&()).unwrap().len(), 0);
+        assert_eq!(crate::from_slice_exact::<()>(&[]).unwrap(), ());
+        assert_eq!(crate::to_vec(&vec![(), ()]).unwrap(), vec![0x00u8, 0x00u8, 0x00u8, 0x02u8]);
+
+        #[derive(Debug, Default, PartialEq)]
+        struct Handle<T> {
+            id: u32,
+            reply: Option<Box<u8>>,
+            marker: PhantomData<T>,
+        }
+
+        serializable_struct!(impl[T] Handle<T> where [], id, reply, marker);
+
+        let h: Handle<String> = Handle { id: 1, reply: None, marker: PhantomData };
+        let bytes = crate::to_vec(&h).unwrap();
+        assert_eq!(bytes, vec![0x00u8, 0x00u8, 0x00u8, 0x01u8, 0x00u8]);
+        assert_eq!(crate::from_slice_exact::<Handle<String>>(&bytes).unwrap(), h);
+    }
+
+    #[test]
+    fn test_serialize_deserialize_time() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let d = Duration::new(1, 5);
+        let bytes = crate::to_vec(&d).unwrap();
+        assert_eq!(bytes, vec![0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x01u8, 0x00u8, 0x00u8, 0x00u8, 0x05u8]);
+        assert_eq!(crate::from_slice_exact::<Duration>(&bytes).unwrap(), d);
+        assert_eq!(crate::from_slice_exact::<Duration>(&crate::to_vec(&Duration::MAX).unwrap()).unwrap(), Duration::MAX);
+
+        let bytes = crate::to_vec(&(1u64, 1_000_000_000u32)).unwrap();
+        let e = crate::from_slice::<Duration>(&bytes).err().unwrap();
+        assert!(matches!(e.root(), Error::InvalidNanos(1_000_000_000)));
+        assert_eq!(e.offset(), Some(12));
+
+        let before = UNIX_EPOCH - Duration::new(1, 500_000_000);
+        let bytes = crate::to_vec(&before).unwrap();
+        assert_eq!(bytes, crate::to_vec(&(-2i64, 500_000_000u32)).unwrap());
+        assert_eq!(crate::to_vec(&UNIX_EPOCH).unwrap(), vec![0x00u8; 12]);
+        assert_eq!(crate::to_vec(&(UNIX_EPOCH - Duration::from_secs(3))).unwrap(), crate::to_vec(&(-3i64, 0u32)).unwrap());
+
+        let now = SystemTime::now();
+        for time in [before, UNIX_EPOCH, now, UNIX_EPOCH + Duration::new(0, 1)] {
+            let mut t = UNIX_EPOCH;
+            t.deserialize(&mut crate::to_vec(&time).unwrap().as_slice()).unwrap();
+            assert_eq!(t, time);
+        }
+
+        let mut t = UNIX_EPOCH;
+        let e = Decoder::new(&crate::to_vec(&(-1i64, u32::MAX)).unwrap()[..]).deserialize(&mut t).err().unwrap();
+        assert!(matches!(e.root(), Error::InvalidNanos(u32::MAX)));
+
+        for time in [Some(before), Some(now), None] {
+            let bytes = crate::to_vec(&time).unwrap();
+            assert_eq!(crate::from_slice_exact::<Option<SystemTime>>(&bytes).unwrap(), time);
+        }
+        let e = crate::from_slice::<Option<SystemTime>>(&crate::to_vec(&Some((0i64, u32::MAX))).unwrap()).err().unwrap();
+        assert!(matches!(e.root(), Error::InvalidNanos(u32::MAX)));
+    }
+
+    #[test]
+    fn test_serialize_deserialize_net() {
+        use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV6};
+
+        let v4 = Ipv4Addr::new(10, 0, 0, 1);
+        assert_eq!(crate::to_vec(&v4).unwrap(), vec![0x0au8, 0x00u8, 0x00u8, 0x01u8]);
+        let addr = SocketAddr::new(IpAddr::V4(v4), 8080);
+        let bytes = crate::to_vec(&addr).unwrap();
+        assert_eq!(bytes, vec![0x00u8, 0x0au8, 0x00u8, 0x00u8, 0x01u8, 0x1fu8, 0x90u8]);
+
+        let v6 = SocketAddr::V6(SocketAddrV6::new(Ipv6Addr::LOCALHOST, 443, 7, 2));
+        let v6_bytes = crate::to_vec(&v6).unwrap();
+        assert_eq!(v6_bytes.len(), 1 + 16 + 2 + 4 + 4);
+        assert_eq!(&v6_bytes[15..], &[0x00u8, 0x01u8, 0x01u8, 0xbbu8, 0x00u8, 0x00u8, 0x00u8, 0x07u8, 0x00u8, 0x00u8, 0x00u8, 0x02u8]);
+
+        let mut a = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 0);
+        for (bytes, expected) in [(&bytes, addr), (&v6_bytes, v6)] {
+            Decoder::new(&bytes[..]).deserialize(&mut a).unwrap();
+            assert_eq!(a, expected);
+        }
+
+        let mut ip = IpAddr::V4(Ipv4Addr::UNSPECIFIED);
+        ip.deserialize(&mut crate::to_vec(&IpAddr::V6(Ipv6Addr::LOCALHOST)).unwrap().as_slice()).unwrap();
+        assert_eq!(ip, IpAddr::V6(Ipv6Addr::LOCALHOST));
+
+        let e = Decoder::new(&[0x04u8, 0x7fu8, 0x00u8, 0x00u8, 0x01u8][..]).deserialize(&mut ip).err().unwrap();
+        assert!(matches!(e.root(), Error::UnknownTag { name: "IpAddr", tag: 4 }));
+        let e = Decoder::new(&[0x02u8][..]).deserialize(&mut a).err().unwrap();
+        assert_eq!(e.to_string(), "unknown tag 2 for `SocketAddr` at byte 1");
+        let e = Decoder::new(&bytes[..6]).deserialize(&mut a).err().unwrap();
+        assert!(matches!(e.root(), Error::UnexpectedEof));
+
+        let addrs = vec![addr, v6, "192.168.1.2:53".parse::<SocketAddr>().unwrap()];
+        let bytes = crate::to_vec(&addrs).unwrap();
+        assert_eq!(crate::from_slice_exact::<Vec<SocketAddr>>(&bytes).unwrap(), addrs);
+        let e = crate::from_slice::<Vec<SocketAddr>>(&bytes[..bytes.len() - 1]).err().unwrap();
+        assert_eq!(e.path(), &[PathSegment::Index(2)]);
+        assert_eq!(crate::from_slice_exact::<(Ipv4Addr, IpAddr)>(&crate::to_vec(&(v4, ip)).unwrap()).unwrap(), (v4, ip));
+    }
+
+    #[test]
+    fn test_serialize_deserialize_paths() {
+        use std::ffi::{CString, OsString};
+        use std::path::{Path, PathBuf};
+
+        let p = PathBuf::from("/tmp/a.txt");
+        let bytes = crate::to_vec(&p).unwrap();
+        assert_eq!(bytes, crate::to_vec(&"/tmp/a.txt").unwrap());
+        assert_eq!(crate::to_vec(&p.as_path()).unwrap(), bytes);
+        assert_eq!(crate::from_slice_exact::<PathBuf>(&bytes).unwrap(), p);
+        assert_eq!(crate::from_slice_exact::<OsString>(&bytes).unwrap(), p.as_os_str());
+        let mut b: Box<Path> = PathBuf::new().into_boxed_path();
+        b.deserialize(&mut bytes.as_slice()).unwrap();
+        assert_eq!(&*b, p.as_path());
+        let paths: Vec<Box<Path>> = vec![p.clone().into_boxed_path(), PathBuf::from("b").into_boxed_path()];
+        assert_eq!(crate::from_slice_exact::<Vec<Box<Path>>>(&crate::to_vec(&paths).unwrap()).unwrap(), paths);
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::ffi::OsStringExt;
+
+            // not UTF-8, so no `String` could hold it
+            let name = PathBuf::from(OsString::from_vec(vec![0x66u8, 0xffu8, 0x6fu8]));
+            let bytes = crate::to_vec(&name).unwrap();
+            assert_eq!(bytes, vec![0x00u8, 0x00u8, 0x00u8, 0x03u8, 0x66u8, 0xffu8, 0x6fu8]);
+            assert_eq!(crate::from_slice_exact::<PathBuf>(&bytes).unwrap(), name);
+            let e = crate::from_slice::<String>(&bytes).err().unwrap();
+            assert!(matches!(e.root(), Error::InvalidUtf8));
+        }
+
+        let c = CString::new("abc").unwrap();
+        let bytes = crate::to_vec(&c).unwrap();
+        assert_eq!(bytes, crate::to_vec(&"abc").unwrap());
+        assert_eq!(crate::to_vec(&c.as_c_str()).unwrap(), bytes);
+        assert_eq!(crate::from_slice_exact::<CString>(&bytes).unwrap(), c);
+        let e = crate::from_slice::<CString>(&crate::to_vec(&"ab\0c").unwrap()).err().unwrap();
+        assert!(matches!(e.root(), Error::InteriorNul(2)));
+        assert_eq!(e.to_string(), "nul byte at position 2 of a c string at byte 8");
+    }
+
+    #[test]
+    fn test_serialize_deserialize_usize_isize() {
+        for v in [0usize, 1, 300, usize::MAX] {
+            let bytes = crate::to_vec(&v).unwrap();
+            assert_eq!(bytes, crate::to_vec(&(v as u64)).unwrap());
+            assert_eq!(crate::from_slice_exact::<usize>(&bytes).unwrap(), v);
+        }
+        for v in [0isize, -1, 300, isize::MIN, isize::MAX] {
+            let bytes = crate::to_vec(&v).unwrap();
+            assert_eq!(bytes, crate::to_vec(&(v as i64)).unwrap());
+            assert_eq!(crate::from_slice_exact::<isize>(&bytes).unwrap(), v);
+        }
+
+        let config = Config::new().with_int_encoding(IntEncoding::Varint);
+        assert_eq!(config.to_vec(&300usize).unwrap(), vec![0xacu8, 0x02u8]);
+        assert_eq!(config.to_vec(&-1isize).unwrap(), vec![0x01u8]);
+        assert_eq!(crate::to_vec(&Varint(vec![1usize])).unwrap(), vec![0x01u8, 0x01u8]);
+        assert_eq!(config.from_slice_exact::<isize>(&[0x01u8]).unwrap(), -1);
+
+        // a value written on a 64-bit host may not fit a narrower one; the
+        // same conversion is checked here with types narrower on every host
+        assert_eq!(crate::narrow::<usize, u64>(5, "usize").unwrap(), 5);
+        let e = crate::narrow::<u16, u64>(u64::MAX, "u16").unwrap_err();
+        assert!(matches!(e, Error::IntegerOverflow { value: 18446744073709551615, target: "u16" }));
+        assert_eq!(e.to_string(), "18446744073709551615 does not fit into `u16`");
+        let e = crate::narrow::<i8, i64>(-129, "i8").unwrap_err();
+        assert!(matches!(e, Error::IntegerOverflow { value: -129, target: "i8" }));
+        assert_eq!(crate::narrow::<i8, i64>(-128, "i8").unwrap(), -128);
+        if usize::BITS < 64 {
+            let e = crate::from_slice::<usize>(&crate::to_vec(&u64::MAX).unwrap()).err().unwrap();
+            assert!(matches!(e.root(), Error::IntegerOverflow { target: "usize", .. }));
+        }
+    }
+
+    #[test]
+    fn test_recursive_types() {
+        #[derive(Debug, Default, PartialEq)]
+        enum Expr {
+            #[default]
+            Nil,
+            Lit(i64),
+            Add(Box<Expr>, Box<Expr>),
+            Neg(Option<Box<Expr>>),
+        }
+
+        serialize_enum!(Expr, u8, { Nil = 0, Lit(v) = 1, Add(a, b) = 2, Neg(e) = 3 });
+        deserialize_enum!(Expr, u8, { Nil = 0, Lit(v) = 1, Add(a, b) = 2, Neg(e) = 3 });
+
+        let x = Expr::Add(Box::new(Expr::Lit(1)), Box::new(Expr::Neg(Some(Box::new(Expr::Lit(2))))));
+        let bytes = crate::to_vec(&x).unwrap();
+        assert_eq!(crate::from_slice_exact::<Expr>(&bytes).unwrap(), x);
+
+        // `Neg(Some(Neg(Some(..))))` a million levels deep stops at the default cap
+        let mut bytes = Vec::new();
+        for _ in 0..1_000_000 {
+            bytes.extend_from_slice(&[0x03u8, 0x01u8]);
+        }
+        let e = crate::from_slice::<Expr>(&bytes).err().unwrap();
+        assert!(matches!(e.root(), Error::DepthExceeded(crate::DEFAULT_MAX_DEPTH)));
+        assert_eq!(e.to_string().split(" at ").next(), Some("values nested deeper than 128 levels"));
+
+        // every `Neg` takes two levels, the `Some` and the `Box`; its field
+        // takes none
+        let config = Config::new().with_limits(Limits::new().with_max_depth(4));
+        let e = config.from_slice::<Expr>(&bytes[..5]).err().unwrap();
+        assert!(matches!(e.root(), Error::UnexpectedEof));
+        let e = config.from_slice::<Expr>(&bytes[..6]).err().unwrap();
+        assert!(matches!(e.root(), Error::DepthExceeded(4)));
+        assert_eq!(e.offset(), Some(6));
+
+        let mut shallow = Expr::Lit(0);
+        for _ in 0..60 {
+            shallow = Expr::Neg(Some(Box::new(shallow)));
+        }
+        assert_eq!(crate::from_slice_exact::<Expr>(&crate::to_vec(&shallow).unwrap()).unwrap(), shallow);
+
+        let config = Config::new().with_limits(Limits::new().with_max_depth(usize::MAX));
+        let mut deep = Expr::Lit(0);
+        for _ in 0..200 {
+            deep = Expr::Neg(Some(Box::new(deep)));
+        }
+        assert!(crate::from_slice::<Expr>(&crate::to_vec(&deep).unwrap()).is_err());
+        assert_eq!(config.from_slice_exact::<Expr>(&crate::to_vec(&deep).unwrap()).unwrap(), deep);
+    }
+
+    #[test]
+    fn test_dyn_impls_inherit_config() {
+        // written like the README, through the dyn-based methods only
+        #[derive(Debug, Default, PartialEq)]
+        struct Node {
+            next: Option<Box<Node>>,
+        }
+
+        impl Serialize for Node {
+            fn serialize(&self, w: &mut dyn Write) -> Result<(), Error> {
+                self.next.serialize(w)
+            }
+        }
+
+        impl DeSerialize for Node {
+            fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Error> {
+                self.next.deserialize(r)
+            }
+        }
+
+        decode_default!(Node);
+
+        let x = Node { next: Some(Box::new(Node { next: None })) };
+        let bytes = crate::to_vec(&x).unwrap();
+        assert_eq!(bytes, vec![0x01u8, 0x00u8]);
+        assert_eq!(crate::from_slice_exact::<Node>(&bytes).unwrap(), x);
+
+        // 1 MB of `Some` stops at the depth cap instead of overflowing the stack
+        let bytes = vec![0x01u8; 1 << 20];
+        let e = crate::from_slice::<Node>(&bytes).err().unwrap();
+        assert!(matches!(e.root(), Error::DepthExceeded(crate::DEFAULT_MAX_DEPTH)));
+        let e = Node::default().deserialize(&mut bytes.as_slice()).unwrap_err();
+        assert!(matches!(e.root(), Error::DepthExceeded(crate::DEFAULT_MAX_DEPTH)));
+        let config = Config::new().with_limits(Limits::new().with_max_depth(4));
+        let e = config.from_slice::<Node>(&bytes).err().unwrap();
+        assert!(matches!(e.root(), Error::DepthExceeded(4)));
+
+        // the config reaches the fields too, and is gone afterwards
+        struct Pair(u16, Vec<u8>);
+
+        impl Serialize for Pair {
+            fn serialize(&self, w: &mut dyn Write) -> Result<(), Error> {
+                self.0.serialize(w)?;
+                self.1.serialize(w)
+            }
+        }
+
+        let config = Config::new().with_endian(Endian::Little).with_length_width(LengthWidth::U8);
+        assert_eq!(config.to_vec(&Pair(1, vec![2])).unwrap(), vec![0x01u8, 0x00u8, 0x01u8, 0x02u8]);
+        assert_eq!(crate::to_vec(&Pair(1, vec![2])).unwrap(), vec![0x00u8, 0x01u8, 0x00u8, 0x00u8, 0x00u8, 0x01u8, 0x02u8]);
+        let mut buf = Vec::new();
+        Pair(1, Vec::new()).serialize(&mut buf).unwrap();
+        assert_eq!(buf, vec![0x00u8, 0x01u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8]);
+
+        // and so does the offset of the decoder they came from
+        #[derive(Debug, Default)]
+        struct Tail {
+            head: u32,
+            at: u64,
+            flag: Option<u8>,
+        }
+
+        impl DeSerialize for Tail {
+            fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Error> {
+                self.head.deserialize(r)?;
+                self.at = Decoder::inherit(&mut *r, |d| Ok(d.offset()))?;
+                self.flag.deserialize(r)
+            }
+        }
+
+        decode_default!(Tail);
+
+        let (_, tail): (u8, Tail) = crate::from_slice(&[0x09u8, 0x00u8, 0x00u8, 0x00u8, 0x07u8, 0x01u8, 0x05u8]).unwrap();
+        assert_eq!((tail.head, tail.at, tail.flag), (7, 5, Some(5)));
+        let e = crate::from_slice::<(u8, Tail)>(&[0x09u8, 0x00u8, 0x00u8, 0x00u8, 0x07u8, 0x02u8]).err().unwrap();
+        assert!(matches!(e.root(), Error::InvalidBool(0x02u8)));
+        assert_eq!(e.offset(), Some(6));
+    }
+
+    #[test]
+    fn test_struct_macro_forms() {
+        #[derive(Debug, Default, PartialEq)]
+        struct Envelope<T> {
+            id: u32,
+            body: Vec<T>
+        }
+
+        serialize_struct!(impl[T] Envelope<T> where [T: Serialize], id, body);
+        deserialize_struct!(impl[T] Envelope<T> where [T: Decode], id, body);
+
+        let x = Envelope { id: 1, body: vec![String::from("a")] };
+        let bytes = crate::to_vec(&x).unwrap();
+        assert_eq!(crate::from_slice_exact::<Envelope<String>>(&bytes).unwrap(), x);
+
+        #[derive(Debug, Default, PartialEq)]
+        struct Pair<A, B>(A, B);
+
+        serialize_struct!(impl[A: Serialize, B: Serialize] Pair<A, B>, 0, 1);
+        deserialize_struct!(impl[A: DeSerialize, B: DeSerialize] Pair<A, B>, 0, 1);
+
+        let p = Pair(7u8, String::from("b"));
+        let bytes = crate::to_vec(&p).unwrap();
+        assert_eq!(bytes, vec![0x07u8, 0x00u8, 0x00u8, 0x00u8, 0x01u8, 0x62u8]);
+        assert_eq!(crate::from_slice_exact::<Pair<u8, String>>(&bytes).unwrap(), p);
+        let e = crate::from_slice::<Pair<u8, String>>(&bytes[..3]).err().unwrap();
+        assert_eq!(e.path(), &[PathSegment::Field("1")]);
+
+        #[derive(Debug, Default, PartialEq)]
+        struct UserId(u64);
+
+        serialize_struct!(transparent UserId);
+        deserialize_struct!(transparent UserId);
+
+        let bytes = crate::to_vec(&UserId(5)).unwrap();
+        assert_eq!(bytes, crate::to_vec(&5u64).unwrap());
+        assert_eq!(crate::from_slice_exact::<UserId>(&bytes).unwrap(), UserId(5));
+        let e = crate::from_slice::<UserId>(&bytes[..3]).err().unwrap();
+        assert!(e.path().is_empty());
+
+        #[derive(Debug, Default, PartialEq)]
+        struct Tagged<T>(Vec<T>);
+
+        serialize_struct!(transparent impl[T] Tagged<T> where [T: Serialize]);
+        deserialize_struct!(transparent impl[T] Tagged<T> where [T: Decode]);
+
+        let bytes = crate::to_vec(&Tagged(vec![1u16, 2u16])).unwrap();
+        assert_eq!(bytes, crate::to_vec(&vec![1u16, 2u16]).unwrap());
+        assert_eq!(crate::from_slice_exact::<Tagged<u16>>(&bytes).unwrap(), Tagged(vec![1u16, 2u16]));
+
+        // parameters named like the generics of the trait methods
+        #[derive(Debug, Default, PartialEq)]
+        struct Frame<R, W> {
+            read: R,
+            written: W,
+        }
+
+        serialize_struct!(impl[R: Serialize, W: Serialize] Frame<R, W>, read, written);
+        deserialize_struct!(impl[R: DeSerialize, W: DeSerialize] Frame<R, W>, read, written);
+
+        #[derive(Debug, Default, PartialEq)]
+        struct Raw<W>(W);
+
+        serialize_struct!(transparent impl[W: Serialize] Raw<W>);
+        deserialize_struct!(transparent impl[W: DeSerialize] Raw<W>);
+
+        let f = Frame { read: Raw(1u8), written: String::from("w") };
+        let mut val: Frame<Raw<u8>, String> = Frame::default();
+        val.deserialize(&mut crate::to_vec(&f).unwrap().as_slice()).unwrap();
+        assert_eq!(val, f);
+        assert_eq!(crate::from_slice_exact::<Frame<Raw<u8>, String>>(&crate::to_vec(&f).unwrap()).unwrap(), f);
+    }
+
+    #[test]
+    fn test_hygienic_macros() {
+        // nothing is imported in here
+        mod plain {
+            #[derive(Debug, Default, PartialEq)]
+            pub struct Xxxx {
+                pub a: i32,
+                pub b: Option<String>
+            }
+
+            crate::serializable_struct!(Xxxx, a, b);
+
+            #[derive(Debug, PartialEq)]
+            pub struct Order {
+                pub id: u32,
+                pub items: Vec<Xxxx>
+            }
+
+            crate::serializable_struct!(decode Order, id, items);
+
+            #[derive(Debug, PartialEq)]
+            pub struct Point(pub u8, pub u8);
+
+            crate::serializable_struct!(decode Point, 0, 1);
+
+            #[derive(Debug, PartialEq)]
+            pub struct Reply<R> {
+                pub id: u32,
+                pub body: R
+            }
+
+            crate::serializable_struct!(decode impl[R] Reply<R> where [R: crate::Serialize + crate::DeSerialize + crate::Decode], id, body);
+
+            #[derive(Debug, Default, PartialEq)]
+            pub struct Wrapper<T>(pub T);
+
+            crate::serializable_struct!(transparent impl[T] Wrapper<T> where [T: crate::Serialize + crate::DeSerialize]);
+
+            #[derive(Debug, PartialEq)]
+            pub enum Op {
+                Push(i64),
+                Pop
+            }
+
+            crate::serialize_enum!(Op, u8, { Push(v) = 0, Pop = 1 });
+            crate::deserialize_enum!(Op, u8, { Push(v) = 0, Pop = 1 });
+        }
+
+        let x = plain::Xxxx { a: 1, b: Some(String::from("b")) };
+        assert_eq!(crate::from_slice_exact::<plain::Xxxx>(&crate::to_vec(&x).unwrap()).unwrap(), x);
+
+        let order = plain::Order { id: 3, items: vec![plain::Xxxx::default()] };
+        let bytes = crate::to_vec(&order).unwrap();
+        assert_eq!(crate::from_slice_exact::<plain::Order>(&bytes).unwrap(), order);
+
+        let reply = plain::Reply { id: 4, body: vec![1u8] };
+        assert_eq!(crate::from_slice_exact::<plain::Reply<Vec<u8>>>(&crate::to_vec(&reply).unwrap()).unwrap(), reply);
+        let e = crate::from_slice::<plain::Order>(&bytes[..10]).err().unwrap();
+        assert_eq!(e.path(), &[PathSegment::Field("items"), PathSegment::Index(0), PathSegment::Field("a")]);
+        let orders = vec![order];
+        assert_eq!(crate::from_slice_exact::<Vec<plain::Order>>(&crate::to_vec(&orders).unwrap()).unwrap(), orders);
+
+        assert_eq!(crate::from_slice_exact::<plain::Point>(&[0x01u8, 0x02u8]).unwrap(), plain::Point(1, 2));
+        assert_eq!(crate::to_vec(&plain::Wrapper(2u16)).unwrap(), vec![0x00u8, 0x02u8]);
+        assert_eq!(crate::to_vec(&plain::Op::Push(1)).unwrap().len(), 9);
+        let mut op = plain::Op::Push(1);
+        op.deserialize(&mut [0x01u8].as_ref()).unwrap();
+        assert_eq!(op, plain::Op::Pop);
+    }
+
+    #[test]
+    fn test_serialize_deserialize_enum() {
+        #[derive(Debug, Default, PartialEq)]
+        enum Shape {
+            #[default]
+            Empty,
+            Circle(u32),
+            Rect { w: u16, h: u16 },
+        }
+
+        serialize_enum!(Shape, u8, {
+            Empty = 0,
+            Circle(r) = 7,
+            Rect { w, h } = 200,
+        });
+        deserialize_enum!(Shape, u8, {
+            Empty = 0,
+            Circle(r) = 7,
+            Rect { w, h } = 200,
+        });
+
+        assert_eq!(crate::to_vec(&Shape::Empty).unwrap(), vec![0x00u8]);
+        assert_eq!(crate::to_vec(&Shape::Circle(1)).unwrap(), vec![0x07u8, 0x00u8, 0x00u8, 0x00u8, 0x01u8]);
+        assert_eq!(crate::to_vec(&Shape::Rect { w: 1, h: 2 }).unwrap(), vec![0xc8u8, 0x00u8, 0x01u8, 0x00u8, 0x02u8]);
+        for shape in [Shape::Empty, Shape::Circle(1), Shape::Rect { w: 1, h: 2 }] {
+            let bytes = crate::to_vec(&shape).unwrap();
+            assert_eq!(crate::from_slice_exact::<Shape>(&bytes).unwrap(), shape);
+        }
+
+        let e = crate::from_slice::<Shape>(&[0x08u8]).err().unwrap();
+        assert!(matches!(e.root(), Error::UnknownTag { name: "Shape", tag: 8 }));
+        assert_eq!(e.to_string(), "unknown tag 8 for `Shape` at byte 1");
+        let e = crate::from_slice::<Shape>(&[0xc8u8, 0x00u8, 0x01u8]).err().unwrap();
+        assert_eq!(e.path(), &[PathSegment::Field("h")]);
+
+        // default u32 tags, and varint tags
+        #[derive(Debug, PartialEq)]
+        enum Op {
+            Push(i64),
+            Pop,
+        }
+
+        serialize_enum!(Op, { Push(v) = 1, Pop = 2 });
+        deserialize_enum!(Op, { Push(v) = 1, Pop = 2 });
+        assert_eq!(crate::to_vec(&Op::Pop).unwrap(), vec![0x00u8, 0x00u8, 0x00u8, 0x02u8]);
+        let mut op = Op::Pop;
+        op.deserialize(&mut crate::to_vec(&Op::Push(-3)).unwrap().as_slice()).unwrap();
+        assert_eq!(op, Op::Push(-3));
+
+        #[derive(Debug, PartialEq)]
+        enum Level {
+            Low,
+            High,
+        }
+
+        serialize_enum!(Level, varint, { Low = 1, High = 300 });
+        deserialize_enum!(Level, varint, { Low = 1, High = 300 });
+        assert_eq!(crate::to_vec(&Level::High).unwrap(), vec![0xacu8, 0x02u8]);
+        let mut level = Level::High;
+        level.deserialize(&mut [0x01u8].as_ref()).unwrap();
+        assert_eq!(level, Level::Low);
+    }
+
+    #[test]
+    fn test_serialize_deserialize_tuple_array() {
+        let t = (1u32, String::from("ab"));
+        let bytes = crate::to_vec(&t).unwrap();
+        assert_eq!(bytes, vec![0x00u8, 0x00u8, 0x00u8, 0x01u8, 0x00u8, 0x00u8, 0x00u8, 0x02u8, 0x61u8, 0x62u8]);
+        assert_eq!(crate::from_slice_exact::<(u32, String)>(&bytes).unwrap(), t);
+        let e = crate::from_slice::<(u32, String)>(&bytes[..9]).err().unwrap();
+        assert_eq!(e.path(), &[PathSegment::Field("1")]);
+
+        let t = (1u8, 2i16, 3u32, 4i64, 5u128, 'x', true, 0.5f32, 0.25f64, String::from("s"), vec![1u8], Some(2u8));
+        let bytes = crate::to_vec(&t).unwrap();
+        assert_eq!(crate::from_slice_exact::<(u8, i16, u32, i64, u128, char, bool, f32, f64, String, Vec<u8>, Option<u8>)>(&bytes).unwrap(), t);
+
+        let hash = [0xabu8; 32];
+        let bytes = crate::to_vec(&hash).unwrap();
+        assert_eq!(bytes, hash.to_vec());
+        assert_eq!(crate::from_slice_exact::<[u8; 32]>(&bytes).unwrap(), hash);
+        let e = crate::from_slice::<[u8; 32]>(&bytes[..31]).err().unwrap();
+        assert!(matches!(e.root(), Error::UnexpectedEof));
+
+        let mut big = [0x0u8; 100];
+        big[99] = 1;
+        let mut out = [0x0u8; 100];
+        out.deserialize(&mut crate::to_vec(&big).unwrap().as_slice()).unwrap();
+        assert_eq!(out[..], big[..]);
+        assert_eq!(crate::from_slice_exact::<[u8; 100]>(&crate::to_vec(&big).unwrap()).unwrap()[..], big[..]);
+
+        let points = [(1u16, 2u16), (3u16, 4u16)];
+        let bytes = crate::to_vec(&points).unwrap();
+        assert_eq!(bytes.len(), 8);
+        assert_eq!(crate::from_slice_exact::<[(u16, u16); 2]>(&bytes).unwrap(), points);
+        let e = crate::from_slice::<[(u16, u16); 2]>(&bytes[..6]).err().unwrap();
+        assert_eq!(e.path(), &[PathSegment::Index(1), PathSegment::Field("1")]);
+
+        // the byte fast path writes the same as the element-wise one
+        assert_eq!(crate::to_vec(&vec![1u8, 2u8]).unwrap(), vec![0x00u8, 0x00u8, 0x00u8, 0x02u8, 0x01u8, 0x02u8]);
+    }
+
+    #[test]
+    fn test_serialize_deserialize_pointers() {
+        use std::borrow::Cow;
+        use std::rc::Rc;
+        use std::sync::Arc;
+
+        let s = String::from("abc");
+        let bytes = crate::to_vec(&s).unwrap();
+        assert_eq!(crate::to_vec(&&s).unwrap(), bytes);
+        assert_eq!(crate::to_vec(&Box::new(s.clone())).unwrap(), bytes);
+        assert_eq!(crate::to_vec(&Rc::new(s.clone())).unwrap(), bytes);
+        assert_eq!(crate::to_vec(&Arc::new(s.clone())).unwrap(), bytes);
+        assert_eq!(crate::to_vec(&Cow::Borrowed("abc")).unwrap(), bytes);
+        assert_eq!(crate::to_vec(&s.clone().into_boxed_str()).unwrap(), bytes);
+        assert_eq!(crate::to_vec(&Arc::<str>::from("abc")).unwrap(), bytes);
+
+        assert_eq!(crate::from_slice_exact::<Box<String>>(&bytes).unwrap(), Box::new(s.clone()));
+        assert_eq!(&*crate::from_slice_exact::<Box<str>>(&bytes).unwrap(), "abc");
+        assert_eq!(crate::from_slice_exact::<Rc<String>>(&bytes).unwrap(), Rc::new(s.clone()));
+        assert_eq!(&*crate::from_slice_exact::<Arc<str>>(&bytes).unwrap(), "abc");
+        assert_eq!(&*crate::from_slice_exact::<Rc<str>>(&bytes).unwrap(), "abc");
+        assert_eq!(crate::from_slice_exact::<Cow<str>>(&bytes).unwrap(), "abc");
+
+        let v = vec![1u16, 2u16];
+        let bytes = crate::to_vec(&v).unwrap();
+        assert_eq!(crate::to_vec(&v.clone().into_boxed_slice()).unwrap(), bytes);
+        assert_eq!(crate::to_vec(&Cow::Borrowed(&v[..])).unwrap(), bytes);
+        let mut b: Box<[u16]> = Box::new([]);
+        b.deserialize(&mut bytes.as_slice()).unwrap();
+        assert_eq!(&b[..], &v[..]);
+        let mut a: Arc<[u16]> = Arc::new([]);
+        a.deserialize(&mut bytes.as_slice()).unwrap();
+        assert_eq!(&a[..], &v[..]);
+
+        // a shared value is replaced rather than written through
+        let shared = Arc::new(5u32);
+        let mut other = shared.clone();
+        other.deserialize(&mut crate::to_vec(&6u32).unwrap().as_slice()).unwrap();
+        assert_eq!((*shared, *other), (5, 6));
+
+        #[derive(Debug, Default, PartialEq)]
+        struct Config {
+            name: Cow<'static, str>,
+            limits: Arc<Vec<u32>>,
+            parent: Option<Box<u8>>
+        }
+
+        serializable_struct!(Config, name, limits, parent);
+
+        let c = Config { name: Cow::Borrowed("main"), limits: Arc::new(vec![1, 2]), parent: Some(Box::new(3)) };
+        let bytes = crate::to_vec(&c).unwrap();
+        assert_eq!(bytes, crate::to_vec(&(String::from("main"), vec![1u32, 2u32], Some(3u8))).unwrap());
+        assert_eq!(crate::from_slice_exact::<Config>(&bytes).unwrap(), c);
+    }
+
+    #[test]
+    fn test_shared_graph() {
+        use std::cell::RefCell;
+        use std::rc::{Rc, Weak};
+        use std::sync::Arc;
+
+        #[derive(Debug, Default)]
+        struct Node {
+            name: String,
+            children: Vec<Rc<RefCell<Node>>>,
+            parent: Weak<RefCell<Node>>,
+        }
+
+        serializable_struct!(Node, name, children, parent);
+
+        let node = |name: &str| Rc::new(RefCell::new(Node { name: String::from(name), ..Node::default() }));
+        let root = node("root");
+        let (a, b) = (node("a"), node("b"));
+        for child in [&a, &b] {
+            child.borrow_mut().parent = Rc::downgrade(&root);
+        }
+        root.borrow_mut().children = vec![a.clone(), b.clone(), a.clone()];
+
+        let config = Config::new().with_graph(true);
+        let bytes = config.to_vec(&root).unwrap();
+        let decoded: Rc<RefCell<Node>> = config.from_slice_exact(&bytes).unwrap();
+        let children = decoded.borrow().children.clone();
+        assert_eq!(children.len(), 3);
+        assert!(Rc::ptr_eq(&children[0], &children[2]));
+        assert!(!Rc::ptr_eq(&children[0], &children[1]));
+        assert_eq!(children[1].borrow().name, "b");
+        for child in &children {
+            assert!(Rc::ptr_eq(&child.borrow().parent.upgrade().unwrap(), &decoded));
+        }
+        // two in the decoded tree, two in `children`
+        assert_eq!(Rc::strong_count(&children[0]), 4);
+
+        // the second `a` is a single back-reference byte
+        root.borrow_mut().children.pop();
+        assert_eq!(config.to_vec(&root).unwrap().len(), bytes.len() - 1);
+
+        // a repeated pointer is one byte; without graph mode it is copied and
+        // `Weak` is refused
+        let pair = (Arc::new(7u32), Arc::new(7u32));
+        let shared = (pair.0.clone(), pair.0.clone());
+        assert_eq!(config.to_vec(&shared).unwrap(), vec![0x01u8, 0x00u8, 0x00u8, 0x00u8, 0x07u8, 0x02u8]);
+        assert_eq!(config.to_vec(&pair).unwrap().len(), 10);
+        let (x, y): (Arc<u32>, Arc<u32>) = config.from_slice_exact(&config.to_vec(&shared).unwrap()).unwrap();
+        assert!(Arc::ptr_eq(&x, &y));
+        let (x, y): (Arc<u32>, Arc<u32>) = crate::from_slice_exact(&crate::to_vec(&shared).unwrap()).unwrap();
+        assert!(!Arc::ptr_eq(&x, &y));
+        assert!(crate::to_vec(&root).is_err());
+
+        // a strong cycle can't be rebuilt
+        a.borrow_mut().children.push(a.clone());
+        let bytes = config.to_vec(&a).unwrap();
+        let e = config.from_slice::<Rc<RefCell<Node>>>(&bytes).err().unwrap();
+        assert!(matches!(e.root(), Error::InvalidReference(2)));
+        a.borrow_mut().children.clear();
+
+        for bytes in [&[0x00u8][..], &[0x02u8][..], &[0x01u8, 0x07u8, 0x03u8][..]] {
+            let e = config.from_slice::<Rc<(u8, Rc<u8>)>>(bytes).err().unwrap();
+            assert!(matches!(e.root(), Error::InvalidReference(_)));
+        }
+        let e = config.from_slice::<(Rc<u8>, Rc<u16>)>(&[0x01u8, 0x05u8, 0x02u8]).err().unwrap();
+        assert!(matches!(e.root(), Error::InvalidReference(2)));
+    }
+
+    #[test]
+    fn test_shared_graph_dyn_impls() {
+        use std::cell::RefCell;
+        use std::rc::{Rc, Weak};
+
+        // only the dyn-based methods, as in the README
+        #[derive(Debug, Default)]
+        struct Node {
+            name: String,
+            children: Vec<Rc<RefCell<Node>>>,
+            parent: Weak<RefCell<Node>>,
+        }
+
+        impl Serialize for Node {
+            fn serialize(&self, w: &mut dyn Write) -> Result<(), Error> {
+                self.name.serialize(w)?;
+                self.children.serialize(w)?;
+                self.parent.serialize(w)
+            }
+        }
+
+        impl DeSerialize for Node {
+            fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Error> {
+                self.name.deserialize(r)?;
+                self.children.deserialize(r)?;
+                self.parent.deserialize(r)
+            }
+        }
+
+        decode_default!(Node);
+
+        let root = Rc::new(RefCell::new(Node { name: String::from("root"), ..Node::default() }));
+        let child = Rc::new(RefCell::new(Node { name: String::from("a"), parent: Rc::downgrade(&root), ..Node::default() }));
+        root.borrow_mut().children = vec![child.clone(), child];
+
+        let config = Config::new().with_graph(true);
+        let bytes = config.to_vec(&root).unwrap();
+        let decoded: Rc<RefCell<Node>> = config.from_slice_exact(&bytes).unwrap();
+        let children = decoded.borrow().children.clone();
+        assert!(Rc::ptr_eq(&children[0], &children[1]));
+        assert_eq!(children[0].borrow().name, "a");
+        assert!(Rc::ptr_eq(&children[0].borrow().parent.upgrade().unwrap(), &decoded));
+    }
+
+    #[test]
+    fn test_length_overflow() {
+        let config = Config::new().with_length_width(LengthWidth::U8);
+        let v: Vec<u8> = vec![0x0u8; 256];
+        match config.to_vec(&v) {
+            Err(Error::LengthOverflow(256)) => {},
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(config.to_vec(&&v[..255]).unwrap().len(), 256);
+        assert_eq!(Error::LengthOverflow(256).to_string(), "length 256 does not fit into the length prefix");
+
+        let config = Config::new().with_length_width(LengthWidth::U64);
+        let bytes = config.to_vec(&"ab").unwrap();
+        assert_eq!(bytes, vec![0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x00u8, 0x02u8, 0x61u8, 0x62u8]);
+        assert_eq!(config.from_slice_exact::<String>(&bytes).unwrap(), "ab");
+        let v: Vec<u16> = vec![1, 2, 3];
+        assert_eq!(config.from_slice_exact::<Vec<u16>>(&config.to_vec(&v).unwrap()).unwrap(), v);
+    }
+
     #[test]
     fn test_serialize_deserialize_struct() {
         #[derive(Debug)]
@@ -1241,7 +4747,7 @@ mod tests {
         }
 
         impl Serialize for Xxxx {
-            fn serialize(&self, w: &mut dyn Write)-> Result<(), Box<dyn Error>> {
+            fn serialize(&self, w: &mut dyn Write)-> Result<(), Error> {
                 self.a.serialize(w)?;
                 self.b.serialize(w)?;
                 self.c.serialize(w)?;
@@ -1250,7 +4756,7 @@ mod tests {
         }
 
         impl DeSerialize for Xxxx {
-            fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Box<dyn Error>> {
+            fn deserialize(&mut self, r: &mut dyn BufRead) -> Result<(), Error> {
                 self.a.deserialize(r)?;
                 self.b.deserialize(r)?;
                 self.c.deserialize(r)?;